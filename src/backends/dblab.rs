@@ -186,6 +186,10 @@ impl DatabaseBranchingBackend for DBLabBackend {
             parent_branch: from_branch.map(|s| s.to_string()),
             database_name: response.clone.snapshot_id,
             state: Some("running".to_string()),
+            server_version: None,
+            port: None,
+            last_used_at: None,
+            size_bytes: None,
         })
     }
 
@@ -223,6 +227,10 @@ impl DatabaseBranchingBackend for DBLabBackend {
                 parent_branch: None,
                 database_name: clone.snapshot_id,
                 state: Some("running".to_string()),
+                server_version: None,
+                port: None,
+                last_used_at: None,
+                size_bytes: None,
             })
             .collect();
 
@@ -269,6 +277,9 @@ impl DatabaseBranchingBackend for DBLabBackend {
             user: db.username,
             password: Some(db.password),
             connection_string: Some(connection_string),
+            sslmode: None,
+            sslrootcert: None,
+            socket_dir: None,
         })
     }
 
@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::docker::{DockerRuntime, PsqlSession};
+
+/// Oldest server with temporary replication slots, which keep the WAL the
+/// backup needs from being recycled while the data is copied.
+const MIN_MAJOR: u32 = 10;
+
+/// A non-exclusive base backup of a running server. Its psql session holds
+/// the backup open while the data directory is copied; dropping it without
+/// [`finish`](Self::finish) ends the session, which aborts the backup.
+pub struct OnlineBackup {
+    session: PsqlSession,
+    major: u32,
+    /// WAL segment the backup's checkpoint is in
+    start_segment: String,
+}
+
+impl OnlineBackup {
+    pub async fn start(
+        runtime: &DockerRuntime,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+        major: u32,
+        slot_name: &str,
+    ) -> Result<Self> {
+        if major < MIN_MAJOR {
+            anyhow::bail!(
+                "online backups need PostgreSQL {} or newer, not {}",
+                MIN_MAJOR,
+                major
+            );
+        }
+
+        let mut session = runtime.psql_session(container_name, pg_user, pg_db).await?;
+        // Temporary: dropped with the session, however it ends
+        session
+            .query(&format!(
+                "SELECT pg_create_physical_replication_slot('{}', true, true)",
+                slot_name
+            ))
+            .await?;
+        let start = if major >= 15 {
+            "pg_backup_start('pgbranch', true)"
+        } else {
+            "pg_start_backup('pgbranch', true, false)"
+        };
+        let start_segment = session
+            .query(&format!("SELECT pg_walfile_name({})", start))
+            .await?
+            .trim()
+            .to_string();
+
+        Ok(Self {
+            session,
+            major,
+            start_segment,
+        })
+    }
+
+    /// End the backup and make `child_data_dir`, copied while it was open,
+    /// recoverable: add the WAL written during the copy and the backup label.
+    pub async fn finish(mut self, parent_data_dir: &Path, child_data_dir: &Path) -> Result<()> {
+        let stop = if self.major >= 15 {
+            "pg_backup_stop(false)"
+        } else {
+            "pg_stop_backup(false, false)"
+        };
+        let output = self
+            .session
+            .query(&format!("SELECT labelfile FROM {}", stop))
+            .await?;
+        let label = output.strip_suffix('\n').unwrap_or(&output).to_string();
+
+        let parent = parent_data_dir.to_path_buf();
+        let child = child_data_dir.to_path_buf();
+        let start_segment = self.start_segment.clone();
+        tokio::task::spawn_blocking(move || complete_clone(&parent, &child, &start_segment, &label))
+            .await
+            .context("backup completion task failed")?
+    }
+}
+
+fn complete_clone(parent: &Path, child: &Path, start_segment: &str, label: &str) -> Result<()> {
+    let parent_wal = parent.join("pg_wal");
+    let child_wal = child.join("pg_wal");
+    let entries = fs::read_dir(&parent_wal)
+        .with_context(|| format!("failed to read {}", parent_wal.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if is_wal_segment(name) && name >= start_segment {
+            fs::copy(entry.path(), child_wal.join(name))
+                .with_context(|| format!("failed to copy WAL segment {}", name))?;
+        }
+    }
+
+    let label_path = child.join("backup_label");
+    fs::write(&label_path, label)
+        .with_context(|| format!("failed to write {}", label_path.display()))?;
+    // The copy carries the parent's lock file, which is not the clone's
+    match fs::remove_file(child.join("postmaster.pid")) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).context("failed to remove postmaster.pid from the clone")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Segment files are named by timeline, log and segment number in hex.
+fn is_wal_segment(name: &str) -> bool {
+    name.len() == 24 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_clone_with_wal_since_backup_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let (parent, child) = (dir.path().join("parent"), dir.path().join("child"));
+        for data in [&parent, &child] {
+            fs::create_dir_all(data.join("pg_wal")).unwrap();
+        }
+        fs::write(parent.join("pg_wal/000000010000000000000002"), "old").unwrap();
+        fs::write(parent.join("pg_wal/000000010000000000000003"), "start").unwrap();
+        fs::write(parent.join("pg_wal/000000010000000000000004"), "during").unwrap();
+        fs::write(child.join("pg_wal/000000010000000000000003"), "stale").unwrap();
+        fs::write(child.join("postmaster.pid"), "1").unwrap();
+
+        complete_clone(&parent, &child, "000000010000000000000003", "LABEL\n").unwrap();
+
+        let read = |name: &str| fs::read_to_string(child.join(name)).ok();
+        assert_eq!(read("pg_wal/000000010000000000000002"), None);
+        assert_eq!(read("pg_wal/000000010000000000000003").unwrap(), "start");
+        assert_eq!(read("pg_wal/000000010000000000000004").unwrap(), "during");
+        assert_eq!(read("backup_label").unwrap(), "LABEL\n");
+        assert!(!child.join("postmaster.pid").exists());
+    }
+}
@@ -1,20 +1,28 @@
 use std::collections::HashMap;
-use std::{collections::HashSet, path::PathBuf, time::Duration};
+use std::pin::Pin;
+use std::{collections::HashSet, ops::RangeInclusive, path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, Context};
+use bollard::container::LogOutput;
 use bollard::exec::StartExecOptions;
 use bollard::models::{
     ContainerCreateBody, ContainerStateStatusEnum, ExecConfig, HostConfig, PortBinding, PortMap,
+    RestartPolicy, RestartPolicyNameEnum,
 };
 use bollard::query_parameters::{
-    CreateContainerOptions, CreateImageOptions, ListContainersOptions, RemoveContainerOptions,
-    StopContainerOptions,
+    BuildImageOptions, CreateContainerOptions, CreateImageOptions, ListContainersOptions,
+    LogsOptions, RemoveContainerOptions, RenameContainerOptions, StatsOptions,
+    StopContainerOptions, WaitContainerOptions,
 };
 use bollard::Docker;
-use futures_util::TryStreamExt;
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::time::{sleep, Instant};
 
-const PGDATA_CONTAINER_PATH: &str = "/var/lib/postgresql/data";
+pub const PGDATA_CONTAINER_PATH: &str = "/var/lib/postgresql/data";
+const SPEC_LABEL: &str = "pgbranch.spec";
+/// Printed after each query of a [`PsqlSession`] to find the end of its output
+const SESSION_MARKER: &str = "__pgbranch_query_done__";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ContainerStatus {
@@ -45,6 +53,104 @@ pub struct StartBranchSpec {
     pub pg_user: String,
     pub pg_password: String,
     pub pg_db: String,
+    pub options: ContainerOptions,
+}
+
+/// A one-off container run by [`DockerRuntime::run_helper`].
+#[derive(Debug, Clone)]
+pub struct HelperSpec {
+    pub image: String,
+    /// Defaults to the host uid:gid, like branch containers
+    pub user: Option<String>,
+    pub env: Vec<String>,
+    pub binds: Vec<String>,
+    pub cmd: Vec<String>,
+}
+
+/// One `docker stats` sample.
+#[derive(Debug, Clone, Default)]
+pub struct ContainerStats {
+    pub cpu_percent: f64,
+    pub memory_bytes: u64,
+    pub memory_limit_bytes: u64,
+}
+
+/// Optional resource limits and extras for a branch container.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContainerOptions {
+    pub memory: Option<i64>,
+    pub nano_cpus: Option<i64>,
+    pub shm_size: Option<i64>,
+    pub restart_policy: Option<RestartPolicyNameEnum>,
+    pub env: Vec<String>,
+    pub labels: HashMap<String, String>,
+    pub mounts: Vec<String>,
+    /// Server settings passed as `postgres -c name=value`
+    pub postgres_settings: Vec<(String, String)>,
+    /// Host address the port is published on; defaults to loopback only
+    pub bind_address: Option<String>,
+    /// Files copied into the container before it first starts
+    pub files: Vec<ContainerFile>,
+    /// `host:ip` entries added to the container's /etc/hosts
+    pub extra_hosts: Vec<String>,
+}
+
+/// A file placed in a branch container, owned by the user the server runs as,
+/// for contents a bind mount would expose with the wrong owner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerFile {
+    pub path: String,
+    pub contents: Vec<u8>,
+    pub mode: u32,
+}
+
+impl StartBranchSpec {
+    /// Fingerprint of everything baked into the container at creation time,
+    /// so a stopped container can be recreated when its settings change.
+    /// Hashes a canonical JSON form, so it stays stable across builds.
+    fn fingerprint(&self) -> String {
+        use sha2::{Digest, Sha256};
+
+        let labels: std::collections::BTreeMap<_, _> = self.options.labels.iter().collect();
+        let mut canonical = serde_json::json!([
+            self.image,
+            self.data_dir,
+            self.port,
+            self.pg_user,
+            self.pg_db,
+            self.options.restart_policy.map(|policy| policy.to_string()),
+            self.options.memory,
+            self.options.nano_cpus,
+            self.options.shm_size,
+            self.options.env,
+            labels,
+            self.options.mounts,
+            self.options.postgres_settings,
+            self.options.bind_address,
+        ]);
+        // Only appended when present, so existing fingerprints stay valid
+        if !self.options.files.is_empty() {
+            let files: Vec<_> = self
+                .options
+                .files
+                .iter()
+                .map(|file| {
+                    let digest = Sha256::digest(&file.contents);
+                    (&file.path, file.mode, format!("{:x}", digest))
+                })
+                .collect();
+            if let Some(parts) = canonical.as_array_mut() {
+                parts.push(serde_json::json!(files));
+            }
+        }
+        if !self.options.extra_hosts.is_empty() {
+            if let Some(parts) = canonical.as_array_mut() {
+                parts.push(serde_json::json!(["extra_hosts", self.options.extra_hosts]));
+            }
+        }
+        let digest = Sha256::digest(canonical.to_string().as_bytes());
+        format!("{:x}", digest)[..16].to_string()
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -62,7 +168,12 @@ impl DockerRuntime {
     pub fn new() -> anyhow::Result<Self> {
         let client =
             Docker::connect_with_local_defaults().context("failed to connect to Docker daemon")?;
-        Ok(Self { client })
+        Ok(Self::with_client(client))
+    }
+
+    /// A runtime talking to the daemon behind `client`.
+    pub fn with_client(client: Docker) -> Self {
+        Self { client }
     }
 
     pub fn client(&self) -> &Docker {
@@ -135,6 +246,252 @@ impl DockerRuntime {
         Ok(())
     }
 
+    pub async fn image_exists(&self, image: &str) -> bool {
+        self.client.inspect_image(image).await.is_ok()
+    }
+
+    /// Build and tag an image from a tar build context. `BASE_IMAGE` is passed
+    /// as a build argument.
+    pub async fn build_image(
+        &self,
+        tag: &str,
+        dockerfile: &str,
+        base_image: &str,
+        context: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let options = BuildImageOptions {
+            dockerfile: dockerfile.to_string(),
+            t: Some(tag.to_string()),
+            rm: true,
+            buildargs: Some(HashMap::from([(
+                "BASE_IMAGE".to_string(),
+                base_image.to_string(),
+            )])),
+            ..Default::default()
+        };
+
+        let mut stream =
+            self.client
+                .build_image(options, None, Some(bollard::body_full(context.into())));
+        while let Some(info) = stream
+            .try_next()
+            .await
+            .with_context(|| format!("failed to build image '{tag}'"))?
+        {
+            if let Some(detail) = info.error_detail {
+                return Err(anyhow!(
+                    "failed to build image '{tag}': {}",
+                    detail.message.unwrap_or_default()
+                ));
+            }
+            if let Some(line) = info.stream {
+                log::debug!("{}", line.trim_end());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// PostgreSQL major version of an official-style image, from its `PG_MAJOR` env.
+    pub async fn image_pg_major(&self, image: &str) -> anyhow::Result<u32> {
+        self.ensure_image(image).await?;
+        let info = self
+            .client
+            .inspect_image(image)
+            .await
+            .with_context(|| format!("failed to inspect image '{image}'"))?;
+        info.config
+            .and_then(|c| c.env)
+            .unwrap_or_default()
+            .iter()
+            .find_map(|var| var.strip_prefix("PG_MAJOR="))
+            .and_then(|major| major.split('.').next()?.parse().ok())
+            .ok_or_else(|| {
+                anyhow!("cannot determine the PostgreSQL major version of '{image}' (no PG_MAJOR)")
+            })
+    }
+
+    /// Run a one-off container to completion and remove it.
+    /// Returns the exit code and the combined output.
+    pub async fn run_helper(&self, spec: &HelperSpec) -> anyhow::Result<(i64, String)> {
+        self.ensure_image(&spec.image).await?;
+
+        let name = format!("pgbranch-helper-{}", uuid::Uuid::new_v4().simple());
+        let config = ContainerCreateBody {
+            image: Some(spec.image.clone()),
+            user: spec.user.clone().or_else(get_host_uid_gid),
+            env: Some(spec.env.clone()),
+            entrypoint: Some(vec![]),
+            cmd: Some(spec.cmd.clone()),
+            labels: Some(HashMap::from([(
+                "pgbranch.managed".to_string(),
+                "true".to_string(),
+            )])),
+            host_config: Some(HostConfig {
+                binds: Some(spec.binds.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.client
+            .create_container(
+                Some(CreateContainerOptions {
+                    name: Some(name.clone()),
+                    ..Default::default()
+                }),
+                config,
+            )
+            .await
+            .with_context(|| format!("failed to create helper container from '{}'", spec.image))?;
+
+        let result = async {
+            self.client
+                .start_container(
+                    &name,
+                    None::<bollard::query_parameters::StartContainerOptions>,
+                )
+                .await
+                .context("failed to start helper container")?;
+
+            let mut exit_code = 0;
+            let mut wait = self
+                .client
+                .wait_container(&name, None::<WaitContainerOptions>);
+            while let Some(response) = wait.next().await {
+                match response {
+                    Ok(response) => exit_code = response.status_code,
+                    Err(bollard::errors::Error::DockerContainerWaitError { code, .. }) => {
+                        exit_code = code
+                    }
+                    Err(e) => return Err(anyhow!("failed to wait for helper container: {e}")),
+                }
+            }
+
+            let output = self
+                .client
+                .logs(
+                    &name,
+                    Some(LogsOptions {
+                        stdout: true,
+                        stderr: true,
+                        ..Default::default()
+                    }),
+                )
+                .try_fold(String::new(), |mut acc, chunk| async move {
+                    acc.push_str(&chunk.to_string());
+                    Ok(acc)
+                })
+                .await
+                .context("failed to read helper container output")?;
+
+            Ok((exit_code, output))
+        }
+        .await;
+
+        if let Err(e) = self.remove_branch(&name).await {
+            log::warn!("Failed to remove helper container '{}': {}", name, e);
+        }
+        result
+    }
+
+    /// Pass a container's log lines to `on_line`, from `since` (unix time) on.
+    /// With `follow`, keeps streaming until the container stops.
+    pub async fn stream_logs(
+        &self,
+        container_name: &str,
+        follow: bool,
+        since: Option<i64>,
+        on_line: &mut (dyn FnMut(&str) + Send),
+    ) -> anyhow::Result<()> {
+        let mut stream = self.client.logs(
+            container_name,
+            Some(LogsOptions {
+                follow,
+                stdout: true,
+                stderr: true,
+                since: since.unwrap_or(0).try_into().unwrap_or(i32::MAX),
+                ..Default::default()
+            }),
+        );
+
+        // Frames usually hold one line, but a long line may span several
+        let mut pending = String::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk =
+                chunk.with_context(|| format!("failed to read logs of '{container_name}'"))?;
+            pending.push_str(&chunk.to_string());
+            drain_lines(&mut pending, on_line);
+        }
+        if !pending.is_empty() {
+            on_line(&pending);
+        }
+        Ok(())
+    }
+
+    /// Sample CPU and memory use the way `docker stats` does. Takes about a
+    /// second, since Docker measures CPU over an interval.
+    pub async fn container_stats(&self, container_name: &str) -> anyhow::Result<ContainerStats> {
+        let stats = self
+            .client
+            .stats(
+                container_name,
+                Some(StatsOptions {
+                    stream: false,
+                    one_shot: false,
+                }),
+            )
+            .next()
+            .await
+            .ok_or_else(|| anyhow!("no stats returned for '{container_name}'"))?
+            .with_context(|| format!("failed to read stats of '{container_name}'"))?;
+
+        let cpu_percent = match (stats.cpu_stats, stats.precpu_stats) {
+            (Some(cpu), Some(precpu)) => {
+                let total = |s: &bollard::models::ContainerCpuStats| {
+                    s.cpu_usage
+                        .as_ref()
+                        .and_then(|u| u.total_usage)
+                        .unwrap_or(0)
+                };
+                let cpu_delta = total(&cpu).saturating_sub(total(&precpu)) as f64;
+                let system_delta = cpu
+                    .system_cpu_usage
+                    .unwrap_or(0)
+                    .saturating_sub(precpu.system_cpu_usage.unwrap_or(0))
+                    as f64;
+                let cpus = cpu.online_cpus.unwrap_or(1).max(1) as f64;
+                if system_delta > 0.0 {
+                    cpu_delta / system_delta * cpus * 100.0
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        };
+
+        let (memory_bytes, memory_limit_bytes) = stats
+            .memory_stats
+            .map(|memory| {
+                // Like `docker stats`, leave out reclaimable page cache
+                let cache = memory
+                    .stats
+                    .as_ref()
+                    .and_then(|s| s.get("inactive_file").copied())
+                    .unwrap_or(0);
+                (
+                    memory.usage.unwrap_or(0).saturating_sub(cache),
+                    memory.limit.unwrap_or(0),
+                )
+            })
+            .unwrap_or_default();
+
+        Ok(ContainerStats {
+            cpu_percent,
+            memory_bytes,
+            memory_limit_bytes,
+        })
+    }
+
     pub async fn container_status(&self, container_name: &str) -> anyhow::Result<ContainerStatus> {
         match self
             .client
@@ -166,6 +523,7 @@ impl DockerRuntime {
 
     pub async fn start_branch(&self, spec: &StartBranchSpec) -> anyhow::Result<()> {
         self.ensure_image(&spec.image).await?;
+        let fingerprint = spec.fingerprint();
 
         match self.container_status(&spec.container_name).await? {
             ContainerStatus::Running => return Ok(()),
@@ -174,16 +532,30 @@ impl DockerRuntime {
                 return Ok(());
             }
             ContainerStatus::Exited | ContainerStatus::Other(_) => {
-                self.client
-                    .start_container(
-                        &spec.container_name,
-                        None::<bollard::query_parameters::StartContainerOptions>,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("failed to start container '{}'", spec.container_name)
-                    })?;
-                return Ok(());
+                if self
+                    .container_label(&spec.container_name, SPEC_LABEL)
+                    .await?
+                    == Some(fingerprint.clone())
+                {
+                    self.client
+                        .start_container(
+                            &spec.container_name,
+                            None::<bollard::query_parameters::StartContainerOptions>,
+                        )
+                        .await
+                        .with_context(|| {
+                            format!("failed to start container '{}'", spec.container_name)
+                        })?;
+                    return Ok(());
+                }
+
+                // Settings changed since the container was created; the data
+                // lives in the bind mount, so recreating it is safe.
+                log::info!(
+                    "Recreating container '{}' with updated settings",
+                    spec.container_name
+                );
+                self.remove_branch(&spec.container_name).await?;
             }
             ContainerStatus::NotFound => {}
         }
@@ -195,26 +567,57 @@ impl DockerRuntime {
         port_bindings.insert(
             "5432/tcp".to_string(),
             Some(vec![PortBinding {
-                host_ip: Some("0.0.0.0".to_string()),
+                host_ip: Some(
+                    spec.options
+                        .bind_address
+                        .clone()
+                        .unwrap_or_else(|| "127.0.0.1".to_string()),
+                ),
                 host_port: Some(spec.port.to_string()),
             }]),
         );
 
-        let mut labels = HashMap::new();
+        let mut labels = spec.options.labels.clone();
         labels.insert("pgbranch.managed".to_string(), "true".to_string());
+        labels.insert(SPEC_LABEL.to_string(), fingerprint);
+
+        let mut env = vec![
+            format!("POSTGRES_USER={}", spec.pg_user),
+            format!("POSTGRES_PASSWORD={}", spec.pg_password),
+            format!("POSTGRES_DB={}", spec.pg_db),
+        ];
+        env.extend(spec.options.env.iter().cloned());
+
+        let mut binds = vec![mount];
+        binds.extend(spec.options.mounts.iter().cloned());
+
+        let cmd = (!spec.options.postgres_settings.is_empty()).then(|| {
+            let mut cmd = vec!["postgres".to_string()];
+            for (name, value) in &spec.options.postgres_settings {
+                cmd.push("-c".to_string());
+                cmd.push(format!("{name}={value}"));
+            }
+            cmd
+        });
 
         let config = ContainerCreateBody {
             image: Some(spec.image.clone()),
+            cmd,
             user: get_host_uid_gid(),
-            env: Some(vec![
-                format!("POSTGRES_USER={}", spec.pg_user),
-                format!("POSTGRES_PASSWORD={}", spec.pg_password),
-                format!("POSTGRES_DB={}", spec.pg_db),
-            ]),
+            env: Some(env),
             labels: Some(labels),
             host_config: Some(HostConfig {
-                binds: Some(vec![mount]),
+                binds: Some(binds),
                 port_bindings: Some(port_bindings),
+                extra_hosts: (!spec.options.extra_hosts.is_empty())
+                    .then(|| spec.options.extra_hosts.clone()),
+                memory: spec.options.memory,
+                nano_cpus: spec.options.nano_cpus,
+                shm_size: spec.options.shm_size,
+                restart_policy: spec.options.restart_policy.map(|name| RestartPolicy {
+                    name: Some(name),
+                    maximum_retry_count: None,
+                }),
                 ..Default::default()
             }),
             ..Default::default()
@@ -229,6 +632,15 @@ impl DockerRuntime {
             .create_container(Some(options), config)
             .await
             .with_context(|| format!("failed to create container '{}'", spec.container_name))?;
+        if !spec.options.files.is_empty() {
+            if let Err(err) = self
+                .upload_files(&spec.container_name, &spec.options.files)
+                .await
+            {
+                let _ = self.remove_branch(&spec.container_name).await;
+                return Err(err);
+            }
+        }
 
         self.client
             .start_container(
@@ -241,6 +653,64 @@ impl DockerRuntime {
         Ok(())
     }
 
+    /// Copy files into a created container, owned by the user it runs as.
+    async fn upload_files(
+        &self,
+        container_name: &str,
+        files: &[ContainerFile],
+    ) -> anyhow::Result<()> {
+        let (uid, gid) = container_owner();
+        let mut builder = tar::Builder::new(Vec::new());
+        for file in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(file.contents.len() as u64);
+            header.set_mode(file.mode);
+            header.set_uid(uid);
+            header.set_gid(gid);
+            header.set_cksum();
+            builder
+                .append_data(
+                    &mut header,
+                    file.path.trim_start_matches('/'),
+                    file.contents.as_slice(),
+                )
+                .with_context(|| format!("failed to archive {}", file.path))?;
+        }
+        let archive = builder.into_inner().context("failed to finish archive")?;
+
+        self.client
+            .upload_to_container(
+                container_name,
+                Some(bollard::query_parameters::UploadToContainerOptions {
+                    path: "/".to_string(),
+                    ..Default::default()
+                }),
+                bollard::body_full(bytes::Bytes::from(archive)),
+            )
+            .await
+            .with_context(|| format!("failed to copy files into container '{container_name}'"))?;
+        Ok(())
+    }
+
+    async fn container_label(
+        &self,
+        container_name: &str,
+        label: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let info = self
+            .client
+            .inspect_container(
+                container_name,
+                None::<bollard::query_parameters::InspectContainerOptions>,
+            )
+            .await
+            .with_context(|| format!("failed to inspect container '{container_name}'"))?;
+        Ok(info
+            .config
+            .and_then(|c| c.labels)
+            .and_then(|mut labels| labels.remove(label)))
+    }
+
     pub async fn stop_branch(&self, container_name: &str) -> anyhow::Result<()> {
         match self.container_status(container_name).await? {
             ContainerStatus::NotFound | ContainerStatus::Exited | ContainerStatus::Other(_) => {
@@ -314,12 +784,60 @@ impl DockerRuntime {
         Ok(())
     }
 
+    pub async fn rename_branch(&self, container_name: &str, new_name: &str) -> anyhow::Result<()> {
+        self.client
+            .rename_container(
+                container_name,
+                RenameContainerOptions {
+                    name: new_name.to_string(),
+                },
+            )
+            .await
+            .with_context(|| {
+                format!("failed to rename container '{container_name}' to '{new_name}'")
+            })?;
+
+        Ok(())
+    }
+
     pub async fn wait_ready(
         &self,
         container_name: &str,
         pg_user: &str,
         pg_db: &str,
         timeout: Duration,
+    ) -> anyhow::Result<()> {
+        self.wait_until_ready(
+            container_name,
+            &["pg_isready", "-U", pg_user, "-d", pg_db],
+            timeout,
+        )
+        .await
+    }
+
+    /// Like [`Self::wait_ready`], but probe over TCP: while a fresh cluster
+    /// runs its init scripts, the entrypoint's temporary server only listens
+    /// on the Unix socket.
+    pub async fn wait_initialized(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        self.wait_until_ready(
+            container_name,
+            &["pg_isready", "-h", "127.0.0.1", "-U", pg_user, "-d", pg_db],
+            timeout,
+        )
+        .await
+    }
+
+    async fn wait_until_ready(
+        &self,
+        container_name: &str,
+        probe: &[&str],
+        timeout: Duration,
     ) -> anyhow::Result<()> {
         let deadline = Instant::now() + timeout;
 
@@ -330,15 +848,13 @@ impl DockerRuntime {
                 ));
             }
 
+            #[allow(clippy::collapsible_match)]
             match self.container_status(container_name).await? {
                 ContainerStatus::NotFound => {
                     return Err(anyhow!("container '{container_name}' does not exist"));
                 }
                 ContainerStatus::Running => {
-                    if self
-                        .exec_check(container_name, &["pg_isready", "-U", pg_user, "-d", pg_db])
-                        .await
-                    {
+                    if self.exec_check(container_name, probe).await {
                         return Ok(());
                     }
                 }
@@ -349,6 +865,103 @@ impl DockerRuntime {
         }
     }
 
+    /// Run a query with psql inside a container and return its unaligned, tuples-only output.
+    pub async fn psql(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+        sql: &str,
+    ) -> anyhow::Result<String> {
+        let (exit_code, stdout, stderr) = self
+            .exec_output(
+                container_name,
+                &[
+                    "psql",
+                    "-U",
+                    pg_user,
+                    "-d",
+                    pg_db,
+                    "-tAX",
+                    "-v",
+                    "ON_ERROR_STOP=1",
+                    "-c",
+                    sql,
+                ],
+            )
+            .await?;
+
+        if exit_code != 0 {
+            return Err(anyhow!(
+                "psql in '{container_name}' failed: {}",
+                stderr.trim()
+            ));
+        }
+
+        Ok(stdout)
+    }
+
+    /// Open a psql session in a container that keeps one connection, and so
+    /// its session state, across queries.
+    pub async fn psql_session(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+    ) -> anyhow::Result<PsqlSession> {
+        let config = ExecConfig {
+            cmd: Some(
+                [
+                    "psql",
+                    "-U",
+                    pg_user,
+                    "-d",
+                    pg_db,
+                    "-tAX",
+                    "-v",
+                    "ON_ERROR_STOP=1",
+                ]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            ),
+            attach_stdin: Some(true),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            ..Default::default()
+        };
+
+        let exec = self
+            .client
+            .create_exec(container_name, config)
+            .await
+            .with_context(|| format!("failed to create exec in '{container_name}'"))?;
+
+        let start_opts = Some(StartExecOptions {
+            detach: false,
+            ..Default::default()
+        });
+        match self.client.start_exec(&exec.id, start_opts).await? {
+            bollard::exec::StartExecResults::Attached { output, input } => Ok(PsqlSession {
+                container_name: container_name.to_string(),
+                input,
+                output,
+            }),
+            bollard::exec::StartExecResults::Detached => {
+                Err(anyhow!("psql session in '{container_name}' did not attach"))
+            }
+        }
+    }
+
+    /// Run a command inside a container. Returns (exit_code, stdout_text, stderr_text).
+    pub async fn exec_output(
+        &self,
+        container_name: &str,
+        cmd: &[&str],
+    ) -> anyhow::Result<(i64, String, String)> {
+        exec_output(&self.client, container_name, cmd).await
+    }
+
     /// Run a command inside a container and return true if it exits successfully.
     async fn exec_check(&self, container_name: &str, cmd: &[&str]) -> bool {
         let config = ExecConfig {
@@ -386,32 +999,140 @@ impl DockerRuntime {
     }
 }
 
-pub async fn pick_available_port(client: &Docker, start_port: u16) -> anyhow::Result<u16> {
-    let docker_ports = docker_published_ports(client).await;
-    let mut port = start_port;
+/// A psql process reading queries from stdin. Dropping it ends the session.
+pub struct PsqlSession {
+    container_name: String,
+    input: Pin<Box<dyn AsyncWrite + Send>>,
+    output: Pin<Box<dyn Stream<Item = Result<LogOutput, bollard::errors::Error>> + Send>>,
+}
+
+impl PsqlSession {
+    /// Run a query and return its unaligned, tuples-only output.
+    pub async fn query(&mut self, sql: &str) -> anyhow::Result<String> {
+        let script = format!("{sql}\n\\echo {SESSION_MARKER}\n");
+        let sent = async {
+            self.input.write_all(script.as_bytes()).await?;
+            self.input.flush().await
+        }
+        .await;
+        sent.with_context(|| format!("failed to send query to psql in '{}'", self.container_name))?;
+
+        let marker = format!("{SESSION_MARKER}\n");
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        loop {
+            match self.output.try_next().await? {
+                Some(LogOutput::StdOut { message }) => {
+                    stdout.push_str(&String::from_utf8_lossy(&message));
+                    if let Some(result) = stdout.strip_suffix(&marker) {
+                        return Ok(result.to_string());
+                    }
+                }
+                Some(LogOutput::StdErr { message }) => {
+                    stderr.push_str(&String::from_utf8_lossy(&message));
+                }
+                Some(_) => {}
+                // ON_ERROR_STOP makes psql exit on the first error
+                None => {
+                    return Err(anyhow!(
+                        "psql in '{}' failed: {}",
+                        self.container_name,
+                        stderr.trim()
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Run a command inside a container. Returns (exit_code, stdout_text, stderr_text).
+pub async fn exec_output(
+    client: &Docker,
+    container_name: &str,
+    cmd: &[&str],
+) -> anyhow::Result<(i64, String, String)> {
+    let config = ExecConfig {
+        cmd: Some(cmd.iter().map(|s| s.to_string()).collect()),
+        attach_stdout: Some(true),
+        attach_stderr: Some(true),
+        ..Default::default()
+    };
+
+    let exec = client
+        .create_exec(container_name, config)
+        .await
+        .with_context(|| format!("failed to create exec in '{container_name}'"))?;
 
-    for _ in 0..1000 {
-        if docker_ports.contains(&port) {
-            port = port.saturating_add(1);
-            if port == u16::MAX {
-                break;
+    let start_opts = Some(StartExecOptions {
+        detach: false,
+        ..Default::default()
+    });
+
+    let mut stdout_buf = Vec::new();
+    let mut stderr_buf = Vec::new();
+    match client.start_exec(&exec.id, start_opts).await? {
+        bollard::exec::StartExecResults::Attached { mut output, .. } => {
+            while let Some(msg) = output.try_next().await? {
+                match msg {
+                    bollard::container::LogOutput::StdOut { message } => {
+                        stdout_buf.extend_from_slice(&message);
+                    }
+                    bollard::container::LogOutput::StdErr { message } => {
+                        stderr_buf.extend_from_slice(&message);
+                    }
+                    _ => {}
+                }
             }
-            continue;
         }
+        bollard::exec::StartExecResults::Detached => {}
+    }
+
+    let inspect = client.inspect_exec(&exec.id).await?;
+    Ok((
+        inspect.exit_code.unwrap_or(-1),
+        String::from_utf8_lossy(&stdout_buf).to_string(),
+        String::from_utf8_lossy(&stderr_buf).to_string(),
+    ))
+}
 
+/// Find a free port in `range`, trying `preferred` first and wrapping around.
+/// Ports in `reserved` (assigned to other branches) are skipped.
+pub async fn pick_available_port(
+    client: &Docker,
+    preferred: u16,
+    range: RangeInclusive<u16>,
+    reserved: &HashSet<u16>,
+) -> anyhow::Result<u16> {
+    let docker_ports = docker_published_ports(client).await;
+    let (start, end) = (*range.start(), *range.end());
+    let size = u32::from(end - start) + 1;
+    let offset = u32::from(preferred.clamp(start, end) - start);
+
+    for i in 0..size {
+        let port = start + ((offset + i) % size) as u16;
+        if reserved.contains(&port) || docker_ports.contains(&port) {
+            continue;
+        }
         if is_port_available(port).await {
             return Ok(port);
         }
+    }
 
-        port = port.saturating_add(1);
-        if port == u16::MAX {
-            break;
-        }
+    Err(anyhow!("no free port in range {start}-{end}"))
+}
+
+/// Whether a port is free on the host and not published by a running container.
+/// Pass each complete line in `pending` to `on_line`, keeping a trailing
+/// partial line for the next frame.
+fn drain_lines(pending: &mut String, on_line: &mut (dyn FnMut(&str) + Send)) {
+    while let Some(end) = pending.find('\n') {
+        on_line(pending[..end].trim_end_matches('\r'));
+        pending.drain(..=end);
     }
+}
 
-    Err(anyhow!(
-        "failed to find available port starting from {start_port}"
-    ))
+pub async fn port_available(client: &Docker, port: u16) -> bool {
+    !docker_published_ports(client).await.contains(&port) && is_port_available(port).await
 }
 
 async fn is_port_available(port: u16) -> bool {
@@ -468,7 +1189,19 @@ fn get_host_uid_gid() -> Option<String> {
     None
 }
 
-fn sanitize(input: &str) -> String {
+/// Owner of files the server must read: the host user branch containers run
+/// as, or the `postgres` user of the official image.
+fn container_owner() -> (u64, u64) {
+    get_host_uid_gid()
+        .and_then(|user| {
+            let (uid, gid) = user.split_once(':')?;
+            Some((uid.parse().ok()?, gid.parse().ok()?))
+        })
+        .unwrap_or((999, 999))
+}
+
+/// Lowercase a name and replace anything but ASCII letters and digits with `-`.
+pub fn sanitize(input: &str) -> String {
     let mut output = String::with_capacity(input.len());
     for ch in input.chars() {
         if ch.is_ascii_alphanumeric() {
@@ -488,3 +1221,57 @@ fn sanitize(input: &str) -> String {
     }
     trimmed
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_lines_are_joined_across_frames() {
+        let mut lines = Vec::new();
+        let mut on_line = |line: &str| lines.push(line.to_string());
+        let mut pending = String::new();
+        for frame in [
+            "LOG:  ready\r\nERR",
+            "OR:  relation \"users\"",
+            " does not exist\nLOG",
+        ] {
+            pending.push_str(frame);
+            drain_lines(&mut pending, &mut on_line);
+        }
+        assert_eq!(
+            lines,
+            ["LOG:  ready", "ERROR:  relation \"users\" does not exist"]
+        );
+        assert_eq!(pending, "LOG");
+    }
+
+    #[test]
+    fn spec_fingerprint_is_stable() {
+        let spec = StartBranchSpec {
+            image: "postgres:17".to_string(),
+            container_name: "pgbranch-app-main".to_string(),
+            data_dir: PathBuf::from("/data/main/pgdata"),
+            port: 55432,
+            pg_user: "postgres".to_string(),
+            pg_password: "secret".to_string(),
+            pg_db: "postgres".to_string(),
+            options: ContainerOptions {
+                restart_policy: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+                labels: HashMap::from([
+                    ("b".to_string(), "2".to_string()),
+                    ("a".to_string(), "1".to_string()),
+                ]),
+                ..Default::default()
+            },
+        };
+        // A changed value here recreates every existing branch container
+        assert_eq!(spec.fingerprint(), "e418ee60b20c925f");
+
+        let moved = StartBranchSpec {
+            port: 55433,
+            ..spec.clone()
+        };
+        assert_ne!(moved.fingerprint(), spec.fingerprint());
+    }
+}
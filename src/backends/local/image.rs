@@ -0,0 +1,473 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use super::docker::DockerRuntime;
+
+/// Debian packages (from the PGDG repository shipped in the official image)
+/// for extensions that are not bundled with PostgreSQL.
+const EXTENSION_PACKAGES: &[(&str, &str, &str)] = &[
+    // (configured name, extension name, package)
+    ("vector", "vector", "postgresql-$PG_MAJOR-pgvector"),
+    ("pgvector", "vector", "postgresql-$PG_MAJOR-pgvector"),
+    ("postgis", "postgis", "postgresql-$PG_MAJOR-postgis-3"),
+    ("pg_partman", "pg_partman", "postgresql-$PG_MAJOR-partman"),
+    ("pg_cron", "pg_cron", "postgresql-$PG_MAJOR-cron"),
+    ("hypopg", "hypopg", "postgresql-$PG_MAJOR-hypopg"),
+    ("pg_repack", "pg_repack", "postgresql-$PG_MAJOR-repack"),
+];
+
+/// Extensions whose library must be in `shared_preload_libraries` before
+/// `CREATE EXTENSION` works.
+const PRELOAD_LIBRARIES: &[&str] = &["pg_cron"];
+
+/// Context hashes kept in the cache before it starts over
+const MAX_CACHED_CONTEXTS: usize = 32;
+
+/// How a project image is derived from the configured base image.
+pub struct ImageRecipe<'a> {
+    pub base_image: &'a str,
+    pub extensions: &'a [String],
+    pub dockerfile: Option<&'a Path>,
+    /// Where build context hashes are cached between runs
+    pub cache_dir: &'a Path,
+}
+
+impl ImageRecipe<'_> {
+    /// Whether the base image can be used as-is.
+    pub fn is_stock(&self) -> bool {
+        self.dockerfile.is_none() && self.packages().is_empty()
+    }
+
+    fn packages(&self) -> Vec<&'static str> {
+        let mut packages: Vec<_> = self
+            .extensions
+            .iter()
+            .filter_map(|name| {
+                EXTENSION_PACKAGES
+                    .iter()
+                    .find(|(configured, _, _)| configured == name)
+                    .map(|(_, _, package)| *package)
+            })
+            .collect();
+        packages.sort_unstable();
+        packages.dedup();
+        packages
+    }
+
+    fn generated_dockerfile(&self) -> String {
+        format!(
+            "FROM {}\n\
+             RUN apt-get update \\\n \
+             && apt-get install -y --no-install-recommends {} \\\n \
+             && rm -rf /var/lib/apt/lists/*\n",
+            self.base_image,
+            self.packages().join(" ")
+        )
+    }
+
+    /// Owned copy of the build inputs, for work on a blocking thread.
+    fn context(&self) -> BuildContext {
+        BuildContext {
+            base_image: self.base_image.to_string(),
+            dockerfile: match self.dockerfile {
+                Some(path) => Dockerfile::File(path.to_path_buf()),
+                None => Dockerfile::Generated(self.generated_dockerfile()),
+            },
+            cache_path: self.cache_dir.join("image-contexts.json"),
+        }
+    }
+}
+
+enum Dockerfile {
+    /// A user Dockerfile; its directory is the build context
+    File(PathBuf),
+    Generated(String),
+}
+
+struct BuildContext {
+    base_image: String,
+    dockerfile: Dockerfile,
+    cache_path: PathBuf,
+}
+
+impl BuildContext {
+    fn dockerfile_name(&self) -> String {
+        match &self.dockerfile {
+            Dockerfile::File(path) => path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "Dockerfile".to_string()),
+            Dockerfile::Generated(_) => "Dockerfile".to_string(),
+        }
+    }
+
+    /// Files of a user build context, relative to its directory, less what
+    /// `.dockerignore` excludes. The Dockerfile itself is always sent.
+    fn files(&self, context_dir: &Path) -> Result<Vec<PathBuf>> {
+        let rules = IgnoreRules::load(context_dir)?;
+        let mut files = Vec::new();
+        collect_files(context_dir, context_dir, &rules, &mut files)?;
+        let dockerfile = PathBuf::from(self.dockerfile_name());
+        if !files.contains(&dockerfile) {
+            files.push(dockerfile);
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Hash of the build inputs, used as the image tag. The files of a user
+    /// context are only read when their sizes or modification times differ
+    /// from the last time they were hashed.
+    fn hash(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.base_image.as_bytes());
+        let context_dir = match &self.dockerfile {
+            Dockerfile::Generated(dockerfile) => {
+                hasher.update(dockerfile.as_bytes());
+                return Ok(format!("{:x}", hasher.finalize()));
+            }
+            Dockerfile::File(path) => path.parent().unwrap_or(Path::new(".")),
+        };
+        hasher.update(self.dockerfile_name().as_bytes());
+
+        let files = self.files(context_dir)?;
+        let mut stat_key = hasher.clone();
+        stat_key.update(context_dir.to_string_lossy().as_bytes());
+        for relative in &files {
+            let path = context_dir.join(relative);
+            let metadata = std::fs::metadata(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|age| age.as_nanos())
+                .unwrap_or_default();
+            stat_key.update(relative.to_string_lossy().as_bytes());
+            stat_key.update(metadata.len().to_le_bytes());
+            stat_key.update(modified.to_le_bytes());
+        }
+        let stat_key = format!("{:x}", stat_key.finalize());
+
+        let mut cache = read_cache(&self.cache_path);
+        if let Some(hash) = cache.get(&stat_key) {
+            return Ok(hash.clone());
+        }
+
+        for relative in &files {
+            let path = context_dir.join(relative);
+            let data = std::fs::read(&path)
+                .with_context(|| format!("failed to read build context file {}", path.display()))?;
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update(&data);
+        }
+        let hash = format!("{:x}", hasher.finalize());
+
+        if cache.len() >= MAX_CACHED_CONTEXTS {
+            cache.clear();
+        }
+        cache.insert(stat_key, hash.clone());
+        if let Err(err) = write_cache(&self.cache_path, &cache) {
+            log::debug!("Failed to cache build context hash: {:#}", err);
+        }
+        Ok(hash)
+    }
+
+    /// Build context as a tar archive.
+    fn archive(&self) -> Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        match &self.dockerfile {
+            Dockerfile::File(path) => {
+                let context_dir = path.parent().unwrap_or(Path::new("."));
+                for relative in self.files(context_dir)? {
+                    // Keeps file modes, so COPY'd scripts stay executable
+                    builder
+                        .append_path_with_name(context_dir.join(&relative), &relative)
+                        .context("failed to add file to build context")?;
+                }
+            }
+            Dockerfile::Generated(dockerfile) => {
+                append_file(&mut builder, Path::new("Dockerfile"), dockerfile.as_bytes())?;
+            }
+        }
+        builder
+            .into_inner()
+            .context("failed to finalize build context archive")
+    }
+}
+
+/// Name of the extension to pass to `CREATE EXTENSION` for a configured entry.
+pub fn extension_name(configured: &str) -> &str {
+    EXTENSION_PACKAGES
+        .iter()
+        .find(|(name, _, _)| *name == configured)
+        .map(|(_, extension, _)| *extension)
+        .unwrap_or(configured)
+}
+
+/// Libraries the configured extensions need preloaded.
+pub fn preload_libraries(extensions: &[String]) -> Vec<&'static str> {
+    PRELOAD_LIBRARIES
+        .iter()
+        .copied()
+        .filter(|library| extensions.iter().any(|e| extension_name(e) == *library))
+        .collect()
+}
+
+/// Return the image to run for a project, building it first if it is not
+/// cached yet. Built images are tagged `pgbranch/<project>:<content hash>`.
+pub async fn ensure_project_image(
+    runtime: &DockerRuntime,
+    project_name: &str,
+    recipe: &ImageRecipe<'_>,
+) -> Result<String> {
+    if recipe.is_stock() {
+        return Ok(recipe.base_image.to_string());
+    }
+
+    let context = std::sync::Arc::new(recipe.context());
+    let hash = {
+        let context = context.clone();
+        tokio::task::spawn_blocking(move || context.hash())
+            .await
+            .context("build context task failed")??
+    };
+    let repository: String = project_name
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let tag = format!("pgbranch/{}:{}", repository, &hash[..12]);
+
+    if runtime.image_exists(&tag).await {
+        return Ok(tag);
+    }
+
+    // The Dockerfile's FROM may use `ARG BASE_IMAGE` to follow the project image
+    runtime.ensure_image(recipe.base_image).await?;
+    let dockerfile = context.dockerfile_name();
+    let archive = {
+        let context = context.clone();
+        tokio::task::spawn_blocking(move || context.archive())
+            .await
+            .context("build context task failed")??
+    };
+    log::info!("Building image {}", tag);
+    runtime
+        .build_image(&tag, &dockerfile, recipe.base_image, archive)
+        .await?;
+
+    Ok(tag)
+}
+
+fn read_cache(path: &Path) -> BTreeMap<String, String> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+fn write_cache(path: &Path, cache: &BTreeMap<String, String>) -> Result<()> {
+    let data = serde_json::to_vec(cache)?;
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, data)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    rules: &IgnoreRules,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read build context {}", dir.display()))?
+    {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        let relative = path.strip_prefix(root)?.to_path_buf();
+        if file_type.is_dir() {
+            // Descend anyway if an exception could re-include something inside
+            if entry.file_name() != ".git"
+                && (!rules.is_ignored(&relative) || rules.has_exceptions())
+            {
+                collect_files(root, &path, rules, files)?;
+            }
+        } else if file_type.is_file() && !rules.is_ignored(&relative) {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+/// Patterns from a `.dockerignore` file. The last pattern that matches a
+/// path decides, and a pattern also matches everything below a directory.
+struct IgnoreRules {
+    /// (pattern, whether it is a `!` exception)
+    rules: Vec<(regex::Regex, bool)>,
+}
+
+impl IgnoreRules {
+    fn load(context_dir: &Path) -> Result<Self> {
+        let path = context_dir.join(".dockerignore");
+        match std::fs::read_to_string(&path) {
+            Ok(content) => Self::parse(&content),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Self::parse(""),
+            Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, exception) = match line.strip_prefix('!') {
+                Some(pattern) => (pattern.trim(), true),
+                None => (line, false),
+            };
+            let pattern = pattern.trim_start_matches("./").trim_start_matches('/');
+            let pattern = pattern.trim_end_matches('/');
+            if pattern.is_empty() {
+                continue;
+            }
+            let regex = regex::Regex::new(&format!("^{}(/.*)?$", pattern_regex(pattern)))
+                .with_context(|| format!("invalid .dockerignore pattern '{}'", line))?;
+            rules.push((regex, exception));
+        }
+        Ok(Self { rules })
+    }
+
+    fn is_ignored(&self, relative: &Path) -> bool {
+        let path = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let mut ignored = false;
+        for (regex, exception) in &self.rules {
+            if regex.is_match(&path) {
+                ignored = !exception;
+            }
+        }
+        ignored
+    }
+
+    fn has_exceptions(&self) -> bool {
+        self.rules.iter().any(|(_, exception)| *exception)
+    }
+}
+
+/// Translate a Go `filepath.Match` pattern, plus `**`, into a regex.
+fn pattern_regex(pattern: &str) -> String {
+    let mut out = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    out.push_str("(.*/)?");
+                } else {
+                    out.push_str(".*");
+                }
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                out.push('[');
+                if chars.peek() == Some(&'^') || chars.peek() == Some(&'!') {
+                    chars.next();
+                    out.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        out.push('\\');
+                    }
+                    out.push(c);
+                }
+                out.push(']');
+            }
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    out.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            other => out.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    out
+}
+
+fn append_file(builder: &mut tar::Builder<Vec<u8>>, path: &Path, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, path, data)
+        .context("failed to add file to build context")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dockerignore_patterns_exclude_with_exceptions() {
+        let rules =
+            IgnoreRules::parse("# comment\nnode_modules\n*.log\n**/tmp\ndocs/\n!docs/keep.md\n")
+                .unwrap();
+        let ignored = |path: &str| rules.is_ignored(Path::new(path));
+        assert!(ignored("node_modules/pkg/index.js"));
+        assert!(ignored("server.log"));
+        assert!(!ignored("logs/server.log"));
+        assert!(ignored("a/b/tmp/file"));
+        assert!(ignored("docs/readme.md"));
+        assert!(!ignored("docs/keep.md"));
+        assert!(!ignored("init.sql"));
+    }
+
+    #[test]
+    fn context_hash_follows_content_and_ignores_excluded_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let context_dir = dir.path().join("context");
+        std::fs::create_dir(&context_dir).unwrap();
+        let dockerfile = context_dir.join("Dockerfile");
+        std::fs::write(&dockerfile, "FROM postgres:17\nCOPY init.sql /\n").unwrap();
+        std::fs::write(context_dir.join("init.sql"), "SELECT 1;").unwrap();
+        std::fs::write(context_dir.join(".dockerignore"), "*.log\n").unwrap();
+
+        let recipe = ImageRecipe {
+            base_image: "postgres:17",
+            extensions: &[],
+            dockerfile: Some(&dockerfile),
+            cache_dir: dir.path(),
+        };
+        let hash = recipe.context().hash().unwrap();
+        assert!(dir.path().join("image-contexts.json").exists());
+
+        std::fs::write(context_dir.join("debug.log"), "noise").unwrap();
+        assert_eq!(recipe.context().hash().unwrap(), hash);
+
+        std::fs::write(context_dir.join("init.sql"), "SELECT 2;").unwrap();
+        assert_ne!(recipe.context().hash().unwrap(), hash);
+    }
+}
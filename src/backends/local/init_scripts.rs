@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Mount point the official postgres image runs init scripts from.
+pub const INITDB_DIR: &str = "/docker-entrypoint-initdb.d";
+
+/// Checksums of the scripts in an init directory, keyed by file name.
+/// Like the image entrypoint, only top-level files are considered.
+pub fn checksums(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut result = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read init scripts directory {}", dir.display()))?
+    {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let data = std::fs::read(entry.path())
+            .with_context(|| format!("failed to read init script {}", entry.path().display()))?;
+        result.insert(
+            entry.file_name().to_string_lossy().to_string(),
+            format!("{:x}", Sha256::digest(&data)),
+        );
+    }
+    Ok(result)
+}
+
+/// Describe differences between the recorded scripts and the current ones.
+pub fn changes(
+    recorded: &BTreeMap<String, String>,
+    current: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (name, checksum) in recorded {
+        match current.get(name) {
+            Some(now) if now == checksum => {}
+            Some(_) => changes.push(format!("{} (modified)", name)),
+            None => changes.push(format!("{} (removed)", name)),
+        }
+    }
+    for name in current.keys() {
+        if !recorded.contains_key(name) {
+            changes.push(format!("{} (added)", name));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_list_modified_removed_and_added_scripts() {
+        let scripts = |entries: &[(&str, &str)]| -> BTreeMap<String, String> {
+            entries
+                .iter()
+                .map(|(name, sum)| (name.to_string(), sum.to_string()))
+                .collect()
+        };
+        let recorded = scripts(&[
+            ("01-roles.sql", "aa"),
+            ("02-schema.sql", "bb"),
+            ("03-seed.sh", "cc"),
+        ]);
+
+        assert!(changes(&recorded, &recorded).is_empty());
+
+        let current = scripts(&[
+            ("01-roles.sql", "aa"),
+            ("02-schema.sql", "b2"),
+            ("04-grants.sql", "dd"),
+        ]);
+        assert_eq!(
+            changes(&recorded, &current),
+            [
+                "02-schema.sql (modified)",
+                "03-seed.sh (removed)",
+                "04-grants.sql (added)",
+            ]
+        );
+    }
+}
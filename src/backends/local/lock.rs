@@ -0,0 +1,119 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// An advisory lock on a project, held by one process at a time so that hooks,
+/// editors and manual commands do not mutate the same branches concurrently.
+/// The lock file records the holder's pid; the lock is released on drop.
+#[derive(Debug)]
+pub struct ProjectLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl ProjectLock {
+    /// Take the lock on `project_name`, waiting for other processes holding it.
+    pub async fn acquire(locks_dir: &Path, project_name: &str) -> Result<Self> {
+        let (mut file, path) = open(locks_dir, project_name)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = match read_holder(&mut file) {
+                    Some(pid) => format!("pid {}", pid),
+                    None => "another process".to_string(),
+                };
+                eprintln!(
+                    "Waiting for lock on project '{}' held by {}...",
+                    project_name, holder
+                );
+                file = tokio::task::spawn_blocking(move || file.lock().map(|()| file))
+                    .await
+                    .context("lock task failed")?
+                    .with_context(|| format!("failed to lock {}", path.display()))?;
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(err).with_context(|| format!("failed to lock {}", path.display()));
+            }
+        }
+
+        let mut lock = Self { file, path };
+        lock.write_holder()?;
+        Ok(lock)
+    }
+
+    /// Take the lock on `project_name` if no other process holds it.
+    pub fn try_acquire(locks_dir: &Path, project_name: &str) -> Result<Option<Self>> {
+        let (file, path) = open(locks_dir, project_name)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(err)) => {
+                return Err(err).with_context(|| format!("failed to lock {}", path.display()));
+            }
+        }
+        let mut lock = Self { file, path };
+        lock.write_holder()?;
+        Ok(Some(lock))
+    }
+
+    fn write_holder(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        write!(self.file, "{}", std::process::id())
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+fn open(locks_dir: &Path, project_name: &str) -> Result<(File, PathBuf)> {
+    std::fs::create_dir_all(locks_dir)
+        .with_context(|| format!("failed to create lock directory {}", locks_dir.display()))?;
+    let path = locks_dir.join(format!("{}.lock", project_name));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("failed to open lock file {}", path.display()))?;
+    Ok((file, path))
+}
+
+fn read_holder(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+impl Drop for ProjectLock {
+    fn drop(&mut self) {
+        // Leave the file behind: removing it would let a waiter lock an unlinked inode
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lock_is_exclusive_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = ProjectLock::acquire(dir.path(), "app").await.unwrap();
+        assert!(ProjectLock::try_acquire(dir.path(), "app")
+            .unwrap()
+            .is_none());
+        assert!(ProjectLock::try_acquire(dir.path(), "other")
+            .unwrap()
+            .is_some());
+
+        drop(lock);
+        assert!(ProjectLock::try_acquire(dir.path(), "app")
+            .unwrap()
+            .is_some());
+    }
+}
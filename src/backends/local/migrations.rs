@@ -0,0 +1,294 @@
+use std::path::Path;
+
+use anyhow::Context;
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+
+/// A schema change, applied once in order of `version`.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&Transaction) -> anyhow::Result<()>,
+}
+
+/// Append new migrations with the next version; never edit released ones.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "baseline schema",
+        apply: baseline,
+    },
+    Migration {
+        version: 2,
+        description: "event history",
+        apply: events,
+    },
+    Migration {
+        version: 3,
+        description: "branch data changes",
+        apply: data_changed_at,
+    },
+];
+
+/// Schema version written by this build of pgbranch.
+pub const LATEST_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+pub fn user_version(conn: &Connection) -> anyhow::Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .context("failed to read SQLite schema version")
+}
+
+/// Bring the database at `path` up to `LATEST_VERSION`. An existing database
+/// is first copied next to itself as `state.db.v<N>.bak`. A database written by
+/// a newer pgbranch is refused rather than touched.
+pub fn migrate(conn: &mut Connection, path: &Path) -> anyhow::Result<()> {
+    let version = user_version(conn)?;
+    if version > LATEST_VERSION {
+        anyhow::bail!(
+            "state database {} has schema version {}, but this pgbranch only supports up to {}; upgrade pgbranch",
+            path.display(),
+            version,
+            LATEST_VERSION
+        );
+    }
+    if version == LATEST_VERSION {
+        return Ok(());
+    }
+
+    let has_tables: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')",
+            [],
+            |row| row.get(0),
+        )
+        .context("failed to inspect state database")?;
+    if has_tables {
+        backup(conn, path, version)?;
+    }
+
+    // IMMEDIATE takes the write lock up front, so a concurrent pgbranch waits
+    // here and then finds the migrations already applied
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Immediate)
+        .context("failed to start schema migration")?;
+    let version = user_version(&tx)?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        log::debug!(
+            "Migrating state database to v{} ({})",
+            migration.version,
+            migration.description
+        );
+        (migration.apply)(&tx).with_context(|| {
+            format!(
+                "failed to migrate state database to v{} ({})",
+                migration.version, migration.description
+            )
+        })?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+    }
+    tx.commit().context("failed to commit schema migration")
+}
+
+fn backup(conn: &Connection, path: &Path, version: u32) -> anyhow::Result<()> {
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(format!(".v{}.bak", version));
+    let backup_path = Path::new(&backup_path);
+    if backup_path.exists() {
+        return Ok(());
+    }
+    conn.execute("VACUUM INTO ?1", [backup_path.to_string_lossy()])
+        .with_context(|| {
+            format!(
+                "failed to back up state database to {}",
+                backup_path.display()
+            )
+        })?;
+    Ok(())
+}
+
+/// The schema as of the first versioned release. Databases from before
+/// versioning may lack any of it, so every step is idempotent.
+fn baseline(tx: &Transaction) -> anyhow::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS projects (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL UNIQUE,
+          image TEXT NOT NULL,
+          storage_backend TEXT NOT NULL DEFAULT 'copy',
+          storage_config TEXT NULL,
+          created_at INTEGER NOT NULL,
+          seed_source TEXT NULL,
+          password TEXT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS branches (
+          id TEXT PRIMARY KEY,
+          project_id TEXT NOT NULL,
+          name TEXT NOT NULL,
+          parent_branch_id TEXT NULL,
+          state TEXT NOT NULL,
+          data_dir TEXT NOT NULL,
+          container_name TEXT NOT NULL,
+          port INTEGER NOT NULL,
+          storage_metadata TEXT NULL,
+          created_at INTEGER NOT NULL,
+          seed_status TEXT NULL,
+          last_used_at INTEGER NULL,
+          image TEXT NULL,
+          port_pinned INTEGER NOT NULL DEFAULT 0,
+          UNIQUE(project_id, name),
+          FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE,
+          FOREIGN KEY(parent_branch_id) REFERENCES branches(id) ON DELETE SET NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS init_scripts (
+          project_id TEXT NOT NULL,
+          name TEXT NOT NULL,
+          checksum TEXT NOT NULL,
+          applied_at INTEGER NOT NULL,
+          PRIMARY KEY(project_id, name),
+          FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS upgrade_snapshots (
+          branch_id TEXT PRIMARY KEY,
+          snapshot_id TEXT NOT NULL,
+          data_dir TEXT NOT NULL,
+          storage_metadata TEXT NULL,
+          image TEXT NOT NULL,
+          created_at INTEGER NOT NULL,
+          FOREIGN KEY(branch_id) REFERENCES branches(id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS branch_settings (
+          branch_id TEXT NOT NULL,
+          name TEXT NOT NULL,
+          value TEXT NOT NULL,
+          PRIMARY KEY(branch_id, name),
+          FOREIGN KEY(branch_id) REFERENCES branches(id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS create_journal (
+          branch_id TEXT PRIMARY KEY,
+          project_id TEXT NOT NULL,
+          branch_name TEXT NOT NULL,
+          steps TEXT NOT NULL,
+          started_at INTEGER NOT NULL,
+          FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE
+        );
+        "#,
+    )?;
+
+    ensure_column(
+        tx,
+        "projects",
+        "storage_backend",
+        "TEXT NOT NULL DEFAULT 'copy'",
+    )?;
+    ensure_column(tx, "projects", "storage_config", "TEXT NULL")?;
+    ensure_column(tx, "projects", "seed_source", "TEXT NULL")?;
+    ensure_column(tx, "projects", "password", "TEXT NULL")?;
+    ensure_column(tx, "branches", "storage_metadata", "TEXT NULL")?;
+    ensure_column(tx, "branches", "seed_status", "TEXT NULL")?;
+    ensure_column(tx, "branches", "last_used_at", "INTEGER NULL")?;
+    ensure_column(tx, "branches", "image", "TEXT NULL")?;
+    ensure_column(tx, "branches", "port_pinned", "INTEGER NOT NULL DEFAULT 0")?;
+    Ok(())
+}
+
+/// Keyed by project name rather than id, so history outlives `destroy`.
+fn events(tx: &Transaction) -> anyhow::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE events (
+          id INTEGER PRIMARY KEY AUTOINCREMENT,
+          project TEXT NOT NULL,
+          branch TEXT NULL,
+          operation TEXT NOT NULL,
+          timestamp INTEGER NOT NULL,
+          os_user TEXT NULL,
+          git_user TEXT NULL,
+          git_branch TEXT NULL,
+          git_commit TEXT NULL,
+          duration_ms INTEGER NOT NULL,
+          outcome TEXT NOT NULL,
+          error TEXT NULL
+        );
+
+        CREATE INDEX events_project_branch ON events(project, branch, id);
+        "#,
+    )?;
+    Ok(())
+}
+
+/// Lets the warm pool tell clones of replaced data from current ones.
+fn data_changed_at(tx: &Transaction) -> anyhow::Result<()> {
+    ensure_column(tx, "branches", "data_changed_at", "INTEGER NULL")
+}
+
+fn ensure_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    column_definition: &str,
+) -> anyhow::Result<()> {
+    let pragma = format!("PRAGMA table_info({table})");
+    let mut stmt = conn.prepare(&pragma)?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for row in rows {
+        if row?.eq_ignore_ascii_case(column) {
+            return Ok(());
+        }
+    }
+
+    let alter = format!("ALTER TABLE {table} ADD COLUMN {column} {column_definition}");
+    conn.execute(&alter, [])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_are_ordered() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+        }
+    }
+
+    #[test]
+    fn migrates_unversioned_database_and_backs_it_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let mut conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE projects (id TEXT PRIMARY KEY, name TEXT NOT NULL UNIQUE, image TEXT NOT NULL, created_at INTEGER NOT NULL);
+             INSERT INTO projects VALUES ('p1', 'app', 'postgres:17', 0);",
+        )
+        .unwrap();
+
+        migrate(&mut conn, &path).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), LATEST_VERSION);
+        let backend: String = conn
+            .query_row("SELECT storage_backend FROM projects", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(backend, "copy");
+        assert!(dir.path().join("state.db.v0.bak").exists());
+
+        // Already current: nothing to do
+        migrate(&mut conn, &path).unwrap();
+    }
+
+    #[test]
+    fn refuses_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let mut conn = Connection::open(&path).unwrap();
+        conn.pragma_update(None, "user_version", LATEST_VERSION + 1)
+            .unwrap();
+
+        let err = migrate(&mut conn, &path).unwrap_err();
+        assert!(err.to_string().contains("upgrade pgbranch"));
+    }
+}
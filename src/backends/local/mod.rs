@@ -1,51 +1,135 @@
+pub mod backup;
 pub mod docker;
+pub mod image;
+pub mod init_scripts;
+pub mod lock;
+pub mod migrations;
 pub mod model;
+pub mod pool;
 pub mod reconcile;
+pub mod repair;
+pub mod replication;
+pub mod saga;
 pub mod seed;
+pub mod settings;
 pub mod state;
 pub mod storage;
+pub mod tls;
+pub mod upgrade;
 
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
 use std::time::Duration;
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
 use super::{
-    BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport, ProjectInfo,
+    BranchExtensions, BranchInfo, ConnectionInfo, CreateBranchOptions, DatabaseBranchingBackend,
+    DoctorCheck, DoctorReport, ExtensionVersion, LogOptions, PortReassignment, PostgresSetting,
+    ProjectInfo, RepairAction, RepairPlan, ResourceUsage, SettingsApplied, SyncStatus,
+    UpgradeOptions, WarmPoolStatus,
 };
-use crate::config::{Config, LocalBackendConfig};
-use docker::{DockerRuntime, ReserveBranchSpec, StartBranchSpec};
-use model::BranchState;
+use crate::config::{
+    parse_duration, parse_size, Config, ContainerConfig, LocalBackendConfig, ReplicationConfig,
+    SeedConfig, WarmPoolConfig,
+};
+use crate::history::BranchEvent;
+use docker::{ContainerOptions, DockerRuntime, ReserveBranchSpec, StartBranchSpec};
+use lock::ProjectLock;
+use model::{BranchState, CreateStep, SeedStatus};
+use saga::CreateSaga;
 use state::{NewBranch, NewProject, Store};
 use storage::StorageCoordinator;
 
 const DEFAULT_IMAGE: &str = "postgres:17";
 const DEFAULT_PORT_RANGE_START: u16 = 55432;
+const DEFAULT_PORT_RANGE_SIZE: u16 = 1000;
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+/// Password of projects created before passwords were generated per project
+const LEGACY_PASSWORD: &str = "postgres";
+/// Default `unix_socket_directories` of the official image
+const SOCKET_CONTAINER_DIR: &str = "/var/run/postgresql";
+/// Longest Unix socket path most platforms accept (`sun_path` less the NUL)
+const MAX_SOCKET_PATH: usize = 103;
 const STARTUP_TIMEOUT: Duration = Duration::from_secs(120);
 
 pub struct LocalBackend {
     project_name: String,
     image: String,
     port_range_start: u16,
+    port_range_end: u16,
     pg_user: String,
-    pg_password: String,
+    pg_password: Option<String>,
     pg_db: String,
+    seed_config: SeedConfig,
+    /// Relative paths in the config resolve against this directory
+    config_dir: PathBuf,
+    replication: Option<ReplicationConfig>,
+    idle_timeout: Option<Duration>,
+    container_options: ContainerOptions,
+    branch_container_options: HashMap<String, ContainerOptions>,
+    extensions: Vec<String>,
+    dockerfile: Option<PathBuf>,
+    init_scripts: Option<PathBuf>,
+    postgres_settings: std::collections::BTreeMap<String, String>,
+    bind_address: String,
+    tls: bool,
+    unix_sockets: bool,
+    warm_pool: Option<WarmPoolConfig>,
+    online_clones: bool,
     store: Mutex<Store>,
+    /// Held while this process mutates the project; shared by nested and
+    /// concurrent operations
+    project_lock: tokio::sync::Mutex<Weak<ProjectLock>>,
+    /// Serializes what concurrent operations share while preparing a
+    /// container: port picks, image builds and certificates
+    setup_lock: tokio::sync::Mutex<()>,
+    /// Serializes clones, which pause their parent or its sync
+    clone_lock: tokio::sync::Mutex<()>,
+    /// When this process started; older create journals are left over
+    started_at: i64,
+    /// Whether unfinished creations were looked for yet
+    recovered: std::sync::atomic::AtomicBool,
+    port_reassignments: Mutex<Vec<PortReassignment>>,
     runtime: DockerRuntime,
     storage: StorageCoordinator,
     data_root: PathBuf,
 }
 
+/// What pausing the sync branch for a clone changed, to undo afterwards.
+#[derive(Debug, Default)]
+struct SyncPause {
+    /// Apply was disabled and must be enabled again
+    resumable: bool,
+    /// The branch was stopped and had to be started
+    started: bool,
+}
+
 impl LocalBackend {
     pub async fn new(
         backend_name: &str,
-        _config: &Config,
+        config: &Config,
+        local_config: Option<&LocalBackendConfig>,
+    ) -> Result<Self> {
+        let runtime = DockerRuntime::new().context("failed to initialize Docker runtime")?;
+        Self::with_runtime(backend_name, config, local_config, runtime).await
+    }
+
+    /// Like [`LocalBackend::new`], with containers managed through `runtime`.
+    pub async fn with_runtime(
+        backend_name: &str,
+        config: &Config,
         local_config: Option<&LocalBackendConfig>,
+        runtime: DockerRuntime,
     ) -> Result<Self> {
+        let config_dir = match &config.config_dir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir().context("Failed to get current directory")?,
+        };
         let image = local_config
             .and_then(|c| c.image.as_deref())
             .unwrap_or(DEFAULT_IMAGE)
@@ -54,22 +138,92 @@ impl LocalBackend {
         let port_range_start = local_config
             .and_then(|c| c.port_range_start)
             .unwrap_or(DEFAULT_PORT_RANGE_START);
+        let port_range_end = local_config
+            .and_then(|c| c.port_range_end)
+            .unwrap_or_else(|| port_range_start.saturating_add(DEFAULT_PORT_RANGE_SIZE - 1));
+        if port_range_end < port_range_start {
+            anyhow::bail!(
+                "invalid local.port_range_end: {} is below port_range_start {}",
+                port_range_end,
+                port_range_start
+            );
+        }
 
         let pg_user = local_config
             .and_then(|c| c.postgres_user.as_deref())
             .unwrap_or("postgres")
             .to_string();
 
-        let pg_password = local_config
-            .and_then(|c| c.postgres_password.as_deref())
-            .unwrap_or("postgres")
-            .to_string();
+        let pg_password = local_config.and_then(|c| c.postgres_password.clone());
 
         let pg_db = local_config
             .and_then(|c| c.postgres_db.as_deref())
             .unwrap_or("postgres")
             .to_string();
 
+        let seed_config = local_config
+            .and_then(|c| c.seed.clone())
+            .unwrap_or_default();
+
+        let replication = local_config.and_then(|c| c.replication.clone());
+
+        let idle_timeout = local_config
+            .and_then(|c| c.idle_timeout.as_deref())
+            .map(parse_duration)
+            .transpose()
+            .context("invalid local.idle_timeout")?;
+
+        let postgres_settings = local_config
+            .map(|c| c.postgres_settings.clone())
+            .unwrap_or_default();
+        for (name, value) in &postgres_settings {
+            validate_setting(name, value).context("invalid local.postgres_settings")?;
+        }
+
+        let extensions = local_config
+            .map(|c| c.extensions.clone())
+            .unwrap_or_default();
+        let dockerfile = local_config
+            .and_then(|c| c.dockerfile.as_deref())
+            .map(|path| std::path::absolute(resolve_path(&config_dir, path)))
+            .transpose()
+            .context("invalid local.dockerfile")?;
+
+        let init_scripts = local_config
+            .and_then(|c| c.init_scripts.as_deref())
+            .map(|path| std::path::absolute(resolve_path(&config_dir, path)))
+            .transpose()
+            .context("invalid local.init_scripts")?;
+
+        let bind_address = local_config
+            .and_then(|c| c.bind_address.as_deref())
+            .unwrap_or(DEFAULT_BIND_ADDRESS)
+            .to_string();
+        bind_address
+            .parse::<std::net::IpAddr>()
+            .with_context(|| format!("invalid local.bind_address '{}'", bind_address))?;
+        let tls = local_config.and_then(|c| c.tls).unwrap_or(false);
+        let unix_sockets = local_config.and_then(|c| c.unix_sockets).unwrap_or(false);
+        let warm_pool = local_config.and_then(|c| c.warm_pool.clone());
+        let online_clones = local_config.and_then(|c| c.online_clones).unwrap_or(true);
+
+        let base_container = local_config
+            .and_then(|c| c.container.clone())
+            .unwrap_or_default();
+        let container_options = resolve_container_options(&base_container, &config_dir)
+            .context("invalid local.container")?;
+        let mut branch_container_options = HashMap::new();
+        for (branch, overrides) in local_config
+            .map(|c| &c.branch_overrides)
+            .into_iter()
+            .flatten()
+        {
+            let options =
+                resolve_container_options(&base_container.merged_with(overrides), &config_dir)
+                    .with_context(|| format!("invalid local.branch_overrides.{}", branch))?;
+            branch_container_options.insert(branch.clone(), options);
+        }
+
         let data_root = if let Some(root) = local_config.and_then(|c| c.data_root.as_deref()) {
             let expanded = shellexpand(root);
             PathBuf::from(expanded)
@@ -94,7 +248,6 @@ impl LocalBackend {
         let store = Store::open(&db_path)
             .with_context(|| format!("failed to open state database: {}", db_path.display()))?;
 
-        let runtime = DockerRuntime::new().context("failed to initialize Docker runtime")?;
         let storage = StorageCoordinator::new(projects_root.clone());
 
         let project_name = backend_name.to_string();
@@ -103,10 +256,32 @@ impl LocalBackend {
             project_name,
             image,
             port_range_start,
+            port_range_end,
             pg_user,
             pg_password,
             pg_db,
+            seed_config,
+            config_dir,
+            replication,
+            idle_timeout,
+            container_options,
+            branch_container_options,
+            extensions,
+            dockerfile,
+            init_scripts,
+            postgres_settings,
+            bind_address,
+            tls,
+            unix_sockets,
+            warm_pool,
+            online_clones,
             store: Mutex::new(store),
+            project_lock: tokio::sync::Mutex::new(Weak::new()),
+            setup_lock: tokio::sync::Mutex::new(()),
+            clone_lock: tokio::sync::Mutex::new(()),
+            started_at: model::now_epoch_millis(),
+            recovered: std::sync::atomic::AtomicBool::new(false),
+            port_reassignments: Mutex::new(Vec::new()),
             runtime,
             storage,
             data_root,
@@ -117,8 +292,44 @@ impl LocalBackend {
         self.store.lock().unwrap()
     }
 
+    /// Lock the project against other pgbranch processes until the guard is
+    /// dropped. Operations nested in one that holds the lock share it.
+    async fn lock_project(&self) -> Result<Arc<ProjectLock>> {
+        // Held while acquiring, so concurrent operations share one lock
+        let mut shared = self.project_lock.lock().await;
+        if let Some(lock) = shared.upgrade() {
+            return Ok(lock);
+        }
+        let lock = Arc::new(
+            ProjectLock::acquire(&self.data_root.join("locks"), &self.project_name).await?,
+        );
+        *shared = Arc::downgrade(&lock);
+        Ok(lock)
+    }
+
+    /// Lock the project unless another process holds it.
+    fn try_lock_project(&self) -> Result<Option<Arc<ProjectLock>>> {
+        // Another operation of this process is taking the lock
+        let Ok(mut shared) = self.project_lock.try_lock() else {
+            return Ok(None);
+        };
+        if let Some(lock) = shared.upgrade() {
+            return Ok(Some(lock));
+        }
+        let Some(lock) =
+            ProjectLock::try_acquire(&self.data_root.join("locks"), &self.project_name)?
+        else {
+            return Ok(None);
+        };
+        let lock = Arc::new(lock);
+        *shared = Arc::downgrade(&lock);
+        Ok(Some(lock))
+    }
+
     async fn ensure_project(&self) -> Result<model::Project> {
-        if let Some(project) = self.store().get_project_by_name(&self.project_name)? {
+        let existing = self.store().get_project_by_name(&self.project_name)?;
+        if let Some(project) = existing {
+            self.recover_at_startup(&project).await;
             return Ok(project);
         }
 
@@ -130,6 +341,8 @@ impl LocalBackend {
             image: self.image.clone(),
             storage_backend: selection.backend,
             storage_config: selection.config,
+            // Only used when no password is configured
+            password: Some(Uuid::new_v4().simple().to_string()),
         })?;
 
         log::info!(
@@ -140,6 +353,43 @@ impl LocalBackend {
         Ok(project)
     }
 
+    /// Superuser password: the configured one, else the project's generated one.
+    fn password(&self, project: &model::Project) -> String {
+        self.pg_password
+            .clone()
+            .or_else(|| project.password.clone())
+            .unwrap_or_else(|| LEGACY_PASSWORD.to_string())
+    }
+
+    /// Address clients connect to: the bind address, or loopback when
+    /// publishing on all interfaces.
+    fn connection_host(&self) -> String {
+        match self.bind_address.parse::<std::net::IpAddr>() {
+            Ok(ip) if ip.is_unspecified() => DEFAULT_BIND_ADDRESS.to_string(),
+            _ => self.bind_address.clone(),
+        }
+    }
+
+    /// Host directory a branch's socket directory is mounted from.
+    fn socket_dir(&self, branch_name: &str) -> PathBuf {
+        self.data_root
+            .join("sockets")
+            .join(docker::sanitize(&self.project_name))
+            .join(docker::sanitize(branch_name))
+    }
+
+    fn tls_dir(&self, project_id: &str) -> PathBuf {
+        self.data_root.join("projects").join(project_id).join("tls")
+    }
+
+    fn settings_dir(&self, branch: &model::Branch) -> PathBuf {
+        self.data_root
+            .join("projects")
+            .join(&branch.project_id)
+            .join("settings")
+            .join(&branch.id)
+    }
+
     async fn reconcile_project(&self, project: &model::Project) -> Result<()> {
         // Read branches from store (sync, releases lock before await)
         let branches = self.store().list_branches(&project.id)?;
@@ -158,38 +408,114 @@ impl LocalBackend {
         Ok(())
     }
 
-    fn connection_uri(&self, port: u16) -> String {
-        format!(
-            "postgresql://{}:{}@127.0.0.1:{}/{}",
-            self.pg_user, self.pg_password, port, self.pg_db
+    /// Image to run for a branch: its image (the project image unless
+    /// overridden), or one built from it with the configured extensions or
+    /// Dockerfile.
+    async fn runtime_image(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+    ) -> Result<String> {
+        image::ensure_project_image(
+            &self.runtime,
+            &self.project_name,
+            &image::ImageRecipe {
+                base_image: branch.image.as_deref().unwrap_or(&project.image),
+                extensions: &self.extensions,
+                dockerfile: self.dockerfile.as_deref(),
+                cache_dir: &self.data_root,
+            },
         )
+        .await
     }
-}
 
-#[async_trait]
-impl DatabaseBranchingBackend for LocalBackend {
-    async fn create_branch(
+    /// Create a branch, optionally on its own image or port, claiming a
+    /// warm pool clone when one matches.
+    async fn create_branch_on(
         &self,
         branch_name: &str,
         from_branch: Option<&str>,
+        create_options: &CreateBranchOptions,
     ) -> Result<BranchInfo> {
+        if pool::is_pool_branch(branch_name) {
+            anyhow::bail!(
+                "Branch names starting with '{}' are reserved for the warm pool",
+                pool::POOL_PREFIX
+            );
+        }
         let project = self.ensure_project().await?;
-        self.reconcile_project(&project).await?;
+        if let Some(info) = self
+            .claim_warm_branch(&project, branch_name, from_branch, create_options)
+            .await?
+        {
+            return Ok(info);
+        }
+
+        let _lock = self.lock_project().await?;
+        self.create_branch_locked(&project, branch_name, from_branch, create_options)
+            .await
+    }
+
+    /// Create a branch under the project lock. Data cloned from a parent on
+    /// another major version is migrated to it. Everything done is undone
+    /// when creation fails or is interrupted by SIGINT or SIGTERM.
+    async fn create_branch_locked(
+        &self,
+        project: &model::Project,
+        branch_name: &str,
+        from_branch: Option<&str>,
+        create_options: &CreateBranchOptions,
+    ) -> Result<BranchInfo> {
+        self.recover_unfinished_creates(project).await?;
+        self.reconcile_project(project).await?;
 
         // Check if branch already exists
         if let Some(existing) = self.store().get_branch_by_name(&project.id, branch_name)? {
             if existing.state == BranchState::Running {
-                return Ok(BranchInfo {
-                    name: existing.name,
-                    created_at: None,
-                    parent_branch: None,
-                    database_name: self.pg_db.clone(),
-                    state: Some(existing.state.as_str().to_string()),
-                });
+                let parent = self.parent_name(project, &existing)?;
+                return Ok(self.branch_info(&existing, parent));
             }
         }
 
         let branch_id = Uuid::new_v4().to_string();
+        let mut saga = CreateSaga::begin(self, &project.id, &branch_id, branch_name)?;
+        let outcome = tokio::select! {
+            result = self.create_branch_steps(
+                project,
+                &mut saga,
+                branch_name,
+                from_branch,
+                create_options,
+            ) => result,
+            signal = saga::interrupted() => {
+                Err(anyhow::anyhow!("Creation of '{}' interrupted by {}", branch_name, signal))
+            }
+        };
+
+        match outcome {
+            Ok(info) => {
+                saga.finish(self)?;
+                Ok(info)
+            }
+            Err(err) => {
+                log::warn!("Rolling back creation of '{}'...", branch_name);
+                self.undo_create(project, saga.journal()).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// The steps of `create_branch_on`, each journaled in `saga` first.
+    async fn create_branch_steps(
+        &self,
+        project: &model::Project,
+        saga: &mut CreateSaga,
+        branch_name: &str,
+        from_branch: Option<&str>,
+        create_options: &CreateBranchOptions,
+    ) -> Result<BranchInfo> {
+        let image = create_options.image.as_deref();
+        let branch_id = saga.journal().branch_id.clone();
         let data_dir = self
             .data_root
             .join("projects")
@@ -207,53 +533,71 @@ impl DatabaseBranchingBackend for LocalBackend {
             })
             .await?;
 
-        let start_port = self.store().next_port()?.max(self.port_range_start);
-        let port = docker::pick_available_port(self.runtime.client(), start_port).await?;
+        let port = match create_options.port {
+            Some(port) => {
+                if self.store().used_ports()?.contains(&port)
+                    || !docker::port_available(self.runtime.client(), port).await
+                {
+                    anyhow::bail!("Port {} is already in use", port);
+                }
+                port
+            }
+            None => self.assign_port(branch_name).await?,
+        };
 
         // Clone or create empty
         let parent = if let Some(from_name) = from_branch {
             self.store().get_branch_by_name(&project.id, from_name)?
         } else {
-            // Try to clone from most recent branch
-            let branches = self.store().list_branches(&project.id)?;
-            branches
-                .into_iter()
-                .find(|b| b.state == BranchState::Running || b.state == BranchState::Stopped)
+            self.default_parent(project)?
         };
 
-        let storage_metadata = if let Some(ref parent_branch) = parent {
-            // Pause parent if running
-            let parent_running = self
-                .runtime
-                .container_status(&parent_branch.container_name)
-                .await?
-                == docker::ContainerStatus::Running;
-
-            if parent_running {
-                self.runtime
-                    .pause_branch(&parent_branch.container_name)
-                    .await?;
+        if let Some(ref parent_branch) = parent {
+            if parent_branch.seed_status == Some(SeedStatus::Failed) {
+                anyhow::bail!(
+                    "Branch '{}' failed seeding or seed verification; refusing to clone from it",
+                    parent_branch.name
+                );
             }
+        }
 
-            let result = self
-                .storage
-                .clone_branch_from_parent(&project, parent_branch, &branch_id, &data_dir)
-                .await;
-
-            if parent_running {
-                self.runtime
-                    .unpause_branch(&parent_branch.container_name)
-                    .await?;
-            }
+        let migration = match (image, &parent) {
+            (Some(image), Some(parent_branch)) => upgrade::Migration::between(
+                upgrade::data_dir_major(Path::new(&parent_branch.data_dir))?,
+                self.runtime.image_pg_major(image).await?,
+            ),
+            _ => upgrade::Migration::None,
+        };
+        let clone_parent = match migration {
+            upgrade::Migration::DumpRestore => None,
+            _ => parent.as_ref(),
+        };
 
-            result?
+        saga.record(
+            self,
+            CreateStep::Storage {
+                data_dir: data_dir.to_string_lossy().to_string(),
+                storage_metadata: None,
+            },
+        )?;
+        let storage_metadata = if let Some(parent_branch) = clone_parent {
+            self.clone_parent_data(
+                project,
+                parent_branch,
+                &branch_id,
+                &data_dir,
+                Some(&mut *saga),
+            )
+            .await?
         } else {
             self.storage
-                .create_empty_branch(&project, &branch_id, &data_dir)
+                .create_empty_branch(project, &branch_id, &data_dir)
                 .await?
         };
+        saga.set_storage_metadata(self, storage_metadata.clone())?;
 
         // Persist to state
+        saga.record(self, CreateStep::Row)?;
         let branch = self.store().create_branch(NewBranch {
             id: branch_id,
             project_id: project.id.clone(),
@@ -264,30 +608,122 @@ impl DatabaseBranchingBackend for LocalBackend {
             container_name: reserved.container_name.clone(),
             port,
             storage_metadata,
+            // Cloned data can only run on the parent's server version
+            image: image
+                .map(str::to_string)
+                .or_else(|| parent.as_ref().and_then(|p| p.image.clone())),
+            port_pinned: create_options.port.is_some(),
         })?;
 
+        let options = self.container_options_for(&branch)?;
+        let init_scripts = match (&parent, &self.init_scripts) {
+            (None, Some(dir)) => Some(init_scripts::checksums(dir)?),
+            _ => None,
+        };
+
+        saga.record(
+            self,
+            CreateStep::Container {
+                container_name: reserved.container_name.clone(),
+            },
+        )?;
+        if let (
+            upgrade::Migration::Upgrade {
+                old_major,
+                new_major,
+            },
+            Some(parent_branch),
+        ) = (migration, &parent)
+        {
+            // The clone may come from a live server: let it recover on the
+            // parent's image and shut down cleanly, as pg_upgrade requires
+            let container_name = reserved.container_name.clone();
+            self.runtime
+                .start_branch(&StartBranchSpec {
+                    image: self.runtime_image(project, parent_branch).await?,
+                    container_name: container_name.clone(),
+                    data_dir: data_dir.clone(),
+                    port,
+                    pg_user: self.pg_user.clone(),
+                    pg_password: self.password(project),
+                    pg_db: self.pg_db.clone(),
+                    options: options.clone(),
+                })
+                .await?;
+            self.runtime
+                .wait_ready(&container_name, &self.pg_user, &self.pg_db, STARTUP_TIMEOUT)
+                .await?;
+            self.runtime.stop_branch(&container_name).await?;
+            self.runtime.remove_branch(&container_name).await?;
+
+            log::info!(
+                "Upgrading '{}' from PostgreSQL {} to {}...",
+                branch_name,
+                old_major,
+                new_major
+            );
+            upgrade::UpgradeJob {
+                runtime: &self.runtime,
+                helper_image: &upgrade::default_helper_image(old_major, new_major),
+                data_dir: &data_dir,
+                old_major,
+                new_major,
+                pg_user: &self.pg_user,
+                // The clone is private to this branch, so hard links are safe
+                mode: upgrade::UpgradeMode::Link,
+            }
+            .run()
+            .await
+            .with_context(|| format!("failed to upgrade branch '{}'", branch_name))?;
+        }
+
         // Start container
         self.runtime
             .start_branch(&StartBranchSpec {
-                image: project.image.clone(),
+                image: self.runtime_image(project, &branch).await?,
                 container_name: reserved.container_name.clone(),
                 data_dir,
                 port,
                 pg_user: self.pg_user.clone(),
-                pg_password: self.pg_password.clone(),
+                pg_password: self.password(project),
                 pg_db: self.pg_db.clone(),
+                options,
             })
             .await?;
 
         // Wait for readiness
-        self.runtime
-            .wait_ready(
-                &reserved.container_name,
-                &self.pg_user,
-                &self.pg_db,
-                STARTUP_TIMEOUT,
-            )
-            .await?;
+        if init_scripts.is_some() {
+            self.runtime
+                .wait_initialized(
+                    &reserved.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    STARTUP_TIMEOUT,
+                )
+                .await?;
+        } else {
+            self.runtime
+                .wait_ready(
+                    &reserved.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    STARTUP_TIMEOUT,
+                )
+                .await?;
+        }
+
+        if let (upgrade::Migration::DumpRestore, Some(parent_branch)) = (migration, &parent) {
+            self.copy_branch_data(project, parent_branch, &reserved.container_name)
+                .await?;
+        }
+        if let Some(parent_branch) = &parent {
+            self.detach_inherited_sync(parent_branch, &reserved.container_name)
+                .await?;
+        }
+        self.ensure_extensions(&reserved.container_name).await?;
+        if let Some(scripts) = init_scripts {
+            self.store().record_init_scripts(&project.id, &scripts)?;
+        }
 
         // Update state
         self.store()
@@ -299,22 +735,44 @@ impl DatabaseBranchingBackend for LocalBackend {
             parent_branch: parent.as_ref().map(|p| p.name.clone()),
             database_name: self.pg_db.clone(),
             state: Some("running".to_string()),
+            server_version: None,
+            port: Some(port),
+            last_used_at: Some(Utc::now()),
+            size_bytes: None,
         })
     }
 
-    async fn delete_branch(&self, branch_name: &str) -> Result<()> {
-        let project = self.ensure_project().await?;
-
-        let branch = self
+    /// Parent of a branch created without `--from`: the most recent branch
+    /// that can be cloned.
+    fn default_parent(&self, project: &model::Project) -> Result<Option<model::Branch>> {
+        Ok(self
             .store()
-            .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .list_branches(&project.id)?
+            .into_iter()
+            .filter(|b| !pool::is_pool_branch(&b.name))
+            .find(|b| {
+                matches!(
+                    b.state,
+                    BranchState::Running | BranchState::Stopped | BranchState::IdleStopped
+                )
+            }))
+    }
+
+    /// Remove a branch's container, data and state.
+    async fn remove_branch(&self, project: &model::Project, branch: &model::Branch) -> Result<()> {
+        self.drop_sync(branch).await;
 
         // Remove container
         self.runtime.remove_branch(&branch.container_name).await?;
 
         // Delete storage data
-        self.storage.delete_branch_data(&project, &branch).await?;
+        self.discard_upgrade_snapshot(project, branch).await?;
+        self.storage.delete_branch_data(project, branch).await?;
+
+        if self.unix_sockets {
+            let _ = tokio::fs::remove_dir_all(self.socket_dir(&branch.name)).await;
+        }
+        let _ = tokio::fs::remove_dir_all(self.settings_dir(branch)).await;
 
         // Delete from state
         self.store().delete_branch(&branch.id)?;
@@ -322,125 +780,812 @@ impl DatabaseBranchingBackend for LocalBackend {
         Ok(())
     }
 
-    async fn list_branches(&self) -> Result<Vec<BranchInfo>> {
-        let project = self.ensure_project().await?;
-        self.reconcile_project(&project).await?;
-
-        let branches = self.store().list_branches(&project.id)?;
-
-        // Build id→name map so we can resolve parent_branch_id to a name
-        let id_to_name: std::collections::HashMap<&str, &str> = branches
-            .iter()
-            .map(|b| (b.id.as_str(), b.name.as_str()))
-            .collect();
-
-        Ok(branches
-            .iter()
-            .map(|b| BranchInfo {
-                name: b.name.clone(),
-                created_at: None,
-                parent_branch: b
-                    .parent_branch_id
-                    .as_deref()
-                    .and_then(|pid| id_to_name.get(pid))
-                    .map(|name| name.to_string()),
-                database_name: self.pg_db.clone(),
-                state: Some(b.state.as_str().to_string()),
-            })
-            .collect())
+    /// Describe a branch for listings; `parent` is the parent branch's name.
+    fn branch_info(&self, branch: &model::Branch, parent: Option<String>) -> BranchInfo {
+        BranchInfo {
+            name: branch.name.clone(),
+            created_at: DateTime::from_timestamp_millis(branch.created_at),
+            parent_branch: parent,
+            database_name: self.pg_db.clone(),
+            state: Some(branch.state.as_str().to_string()),
+            server_version: upgrade::data_dir_major(Path::new(&branch.data_dir))
+                .ok()
+                .map(|major| major.to_string()),
+            port: Some(branch.port),
+            last_used_at: branch
+                .last_used_at
+                .and_then(DateTime::from_timestamp_millis),
+            size_bytes: None,
+        }
     }
 
-    async fn branch_exists(&self, branch_name: &str) -> Result<bool> {
-        let project = self.ensure_project().await?;
+    fn parent_name(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+    ) -> Result<Option<String>> {
+        let Some(parent_id) = &branch.parent_branch_id else {
+            return Ok(None);
+        };
         Ok(self
             .store()
-            .get_branch_by_name(&project.id, branch_name)?
-            .is_some())
+            .list_branches(&project.id)?
+            .into_iter()
+            .find(|b| &b.id == parent_id)
+            .map(|b| b.name))
     }
 
-    async fn switch_to_branch(&self, branch_name: &str) -> Result<BranchInfo> {
-        let project = self.ensure_project().await?;
-        self.reconcile_project(&project).await?;
+    /// Clone a parent's data for a branch, pausing its sync for a consistent
+    /// copy. Steps to undo are journaled in `saga` when one is given. Returns
+    /// the storage metadata of the clone.
+    async fn clone_parent_data(
+        &self,
+        project: &model::Project,
+        parent: &model::Branch,
+        branch_id: &str,
+        data_dir: &Path,
+        mut saga: Option<&mut CreateSaga>,
+    ) -> Result<Option<String>> {
+        let paused_sync = CreateStep::PausedSync {
+            parent_branch: parent.name.clone(),
+        };
+        if let Some(saga) = saga.as_deref_mut() {
+            saga.record(self, paused_sync.clone())?;
+        }
+        let sync_pause = self.pause_sync_for_clone(project, parent).await?;
 
-        let branch = self
-            .store()
-            .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+        let result = self
+            .clone_running_parent(project, parent, branch_id, data_dir, saga.as_deref_mut())
+            .await;
 
-        // Start if stopped
-        if branch.state == BranchState::Stopped {
-            self.runtime
-                .start_branch(&StartBranchSpec {
-                    image: project.image.clone(),
-                    container_name: branch.container_name.clone(),
-                    data_dir: PathBuf::from(&branch.data_dir),
-                    port: branch.port,
-                    pg_user: self.pg_user.clone(),
-                    pg_password: self.pg_password.clone(),
-                    pg_db: self.pg_db.clone(),
-                })
-                .await?;
+        self.end_sync_pause(parent, sync_pause).await;
+        if let Some(saga) = saga {
+            saga.resolve(self, &paused_sync)?;
+        }
+        result
+    }
 
-            self.runtime
-                .wait_ready(
-                    &branch.container_name,
-                    &self.pg_user,
-                    &self.pg_db,
-                    STARTUP_TIMEOUT,
-                )
-                .await?;
-            self.store()
-                .update_branch_state(&branch.id, BranchState::Running)?;
+    /// Clone a parent that may be running without stopping it from serving:
+    /// ZFS snapshots are atomic, and other storage copies the data within an
+    /// online backup. When no backup can be taken (or `online_clones` is off)
+    /// the parent is paused for the copy instead.
+    async fn clone_running_parent(
+        &self,
+        project: &model::Project,
+        parent: &model::Branch,
+        branch_id: &str,
+        data_dir: &Path,
+        mut saga: Option<&mut CreateSaga>,
+    ) -> Result<Option<String>> {
+        let parent_running = self
+            .runtime
+            .container_status(&parent.container_name)
+            .await?
+            == docker::ContainerStatus::Running;
+        if !parent_running || project.storage_backend == model::StorageBackend::Zfs {
+            return self
+                .storage
+                .clone_branch_from_parent(project, parent, branch_id, data_dir)
+                .await;
         }
 
-        Ok(BranchInfo {
-            name: branch.name,
-            created_at: None,
-            parent_branch: None,
-            database_name: self.pg_db.clone(),
-            state: Some("running".to_string()),
-        })
-    }
+        if self.online_clones {
+            match self.start_online_backup(parent, branch_id).await {
+                Ok(backup) => {
+                    match self
+                        .storage
+                        .clone_branch_from_parent(project, parent, branch_id, data_dir)
+                        .await
+                    {
+                        Ok(metadata) => {
+                            backup
+                                .finish(Path::new(&parent.data_dir), data_dir)
+                                .await
+                                .with_context(|| {
+                                    format!("failed to finish backup of '{}'", parent.name)
+                                })?;
+                            return Ok(metadata);
+                        }
+                        // Files the server removes or renames mid-copy can fail it
+                        Err(err) => log::warn!(
+                            "Copy of running '{}' failed, pausing it to copy again: {:#}",
+                            parent.name,
+                            err
+                        ),
+                    }
+                }
+                Err(err) => log::warn!(
+                    "Cannot take an online backup of '{}', pausing it for the clone: {:#}",
+                    parent.name,
+                    err
+                ),
+            }
+        }
 
-    async fn get_connection_info(&self, branch_name: &str) -> Result<ConnectionInfo> {
-        let project = self.ensure_project().await?;
+        let paused_parent = CreateStep::PausedParent {
+            container_name: parent.container_name.clone(),
+        };
+        if let Some(saga) = saga.as_deref_mut() {
+            saga.record(self, paused_parent.clone())?;
+        }
+        self.runtime.pause_branch(&parent.container_name).await?;
 
-        let branch = self
-            .store()
-            .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+        let result = self
+            .storage
+            .clone_branch_from_parent(project, parent, branch_id, data_dir)
+            .await;
 
-        Ok(ConnectionInfo {
-            host: "127.0.0.1".to_string(),
-            port: branch.port,
-            database: self.pg_db.clone(),
-            user: self.pg_user.clone(),
-            password: Some(self.pg_password.clone()),
-            connection_string: Some(self.connection_uri(branch.port)),
-        })
+        self.runtime.unpause_branch(&parent.container_name).await?;
+        if let Some(saga) = saga {
+            saga.resolve(self, &paused_parent)?;
+        }
+        result
     }
 
-    async fn start_branch(&self, branch_name: &str) -> Result<()> {
-        let project = self.ensure_project().await?;
-
-        let branch = self
-            .store()
-            .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+    async fn start_online_backup(
+        &self,
+        parent: &model::Branch,
+        branch_id: &str,
+    ) -> Result<backup::OnlineBackup> {
+        let major = upgrade::data_dir_major(Path::new(&parent.data_dir))?;
+        backup::OnlineBackup::start(
+            &self.runtime,
+            &parent.container_name,
+            &self.pg_user,
+            &self.pg_db,
+            major,
+            &format!("pgbranch_clone_{}", branch_id.replace('-', "_")),
+        )
+        .await
+    }
 
-        self.runtime
-            .start_branch(&StartBranchSpec {
-                image: project.image.clone(),
-                container_name: branch.container_name.clone(),
-                data_dir: PathBuf::from(&branch.data_dir),
-                port: branch.port,
-                pg_user: self.pg_user.clone(),
-                pg_password: self.pg_password.clone(),
-                pg_db: self.pg_db.clone(),
-            })
-            .await?;
+    /// Copy a branch's database into another branch's container with a logical
+    /// dump. A stopped source is started for the copy and stopped again.
+    async fn copy_branch_data(
+        &self,
+        project: &model::Project,
+        source: &model::Branch,
+        container_name: &str,
+    ) -> Result<()> {
+        let was_running = self
+            .runtime
+            .container_status(&source.container_name)
+            .await?
+            == docker::ContainerStatus::Running;
+        if !was_running {
+            self.wake_branch(project, source).await?;
+        }
 
-        self.runtime
+        log::info!("Copying data from '{}' with pg_dump...", source.name);
+        let result = seed::seed_from_branch(
+            self.runtime.client(),
+            &source.container_name,
+            container_name,
+            &self.pg_user,
+            &self.pg_db,
+        )
+        .await;
+
+        if !was_running {
+            self.runtime.stop_branch(&source.container_name).await?;
+            self.store().update_branch_state(&source.id, source.state)?;
+        }
+        result
+    }
+
+    /// Branches targeted by an upgrade command: one by name, or all of them.
+    fn upgrade_targets(
+        &self,
+        project: &model::Project,
+        branch_name: Option<&str>,
+    ) -> Result<Vec<model::Branch>> {
+        match branch_name {
+            Some(name) => Ok(vec![self
+                .store()
+                .get_branch_by_name(&project.id, name)?
+                .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", name))?]),
+            None => self.store().list_branches(&project.id),
+        }
+    }
+
+    /// Upgrade one branch's data in place, keeping a CoW snapshot of the old
+    /// data for `upgrade --rollback`.
+    async fn upgrade_branch(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+        image: &str,
+        new_major: u32,
+        options: &UpgradeOptions,
+    ) -> Result<()> {
+        let data_dir = PathBuf::from(&branch.data_dir);
+        let old_major = upgrade::data_dir_major(&data_dir)?;
+        let old_image = branch
+            .image
+            .clone()
+            .unwrap_or_else(|| project.image.clone());
+        if new_major < old_major {
+            anyhow::bail!(
+                "Branch '{}' runs PostgreSQL {}; downgrading to {} is not supported",
+                branch.name,
+                old_major,
+                new_major
+            );
+        }
+
+        let was_running = self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            == docker::ContainerStatus::Running;
+        // pg_upgrade needs a clean shutdown; the container is recreated on the
+        // new image at the next start
+        self.runtime.stop_branch(&branch.container_name).await?;
+        self.runtime.remove_branch(&branch.container_name).await?;
+
+        if new_major > old_major {
+            let mode = upgrade::UpgradeMode::from_str(&options.mode).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid upgrade mode '{}': use link, clone or copy",
+                    options.mode
+                )
+            })?;
+            let helper_image = options
+                .helper_image
+                .clone()
+                .unwrap_or_else(|| upgrade::default_helper_image(old_major, new_major));
+
+            self.discard_upgrade_snapshot(project, branch).await?;
+            let snapshot_id = Uuid::new_v4().to_string();
+            let snapshot_dir = self
+                .data_root
+                .join("projects")
+                .join(&project.id)
+                .join("branches")
+                .join(&snapshot_id)
+                .join("pgdata");
+            let snapshot_metadata = self
+                .storage
+                .clone_branch_from_parent(project, branch, &snapshot_id, &snapshot_dir)
+                .await
+                .context("failed to snapshot branch before upgrade")?;
+            self.store()
+                .save_upgrade_snapshot(&model::UpgradeSnapshot {
+                    branch_id: branch.id.clone(),
+                    snapshot_id,
+                    data_dir: snapshot_dir.to_string_lossy().to_string(),
+                    storage_metadata: snapshot_metadata,
+                    image: old_image,
+                    created_at: model::now_epoch_millis(),
+                })?;
+
+            log::info!(
+                "Upgrading '{}' from PostgreSQL {} to {}...",
+                branch.name,
+                old_major,
+                new_major
+            );
+            upgrade::UpgradeJob {
+                runtime: &self.runtime,
+                helper_image: &helper_image,
+                data_dir: &data_dir,
+                old_major,
+                new_major,
+                pg_user: &self.pg_user,
+                mode,
+            }
+            .run()
+            .await
+            .with_context(|| format!("failed to upgrade branch '{}'", branch.name))?;
+        }
+
+        {
+            let store = self.store();
+            store.update_branch_image(&branch.id, Some(image))?;
+            store.mark_branch_data_changed(&branch.id)?;
+        }
+        let branch = model::Branch {
+            image: Some(image.to_string()),
+            ..branch.clone()
+        };
+
+        if was_running {
+            self.wake_branch(project, &branch).await?;
+            if new_major > old_major {
+                // pg_upgrade does not carry over planner statistics before 18
+                let analyze = self
+                    .runtime
+                    .exec_output(
+                        &branch.container_name,
+                        &[
+                            "vacuumdb",
+                            "-U",
+                            &self.pg_user,
+                            "--all",
+                            "--analyze-in-stages",
+                        ],
+                    )
+                    .await;
+                if let Err(e) = analyze {
+                    log::warn!("Failed to analyze '{}' after upgrade: {}", branch.name, e);
+                }
+            }
+        } else {
+            self.store()
+                .update_branch_state(&branch.id, BranchState::Stopped)?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete a branch's pre-upgrade snapshot, if it has one.
+    async fn discard_upgrade_snapshot(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+    ) -> Result<bool> {
+        let Some(snapshot) = self.store().get_upgrade_snapshot(&branch.id)? else {
+            return Ok(false);
+        };
+
+        let snapshot_branch = model::Branch {
+            id: snapshot.snapshot_id.clone(),
+            data_dir: snapshot.data_dir.clone(),
+            storage_metadata: snapshot.storage_metadata.clone(),
+            ..branch.clone()
+        };
+        self.storage
+            .delete_branch_data(project, &snapshot_branch)
+            .await?;
+        self.store().delete_upgrade_snapshot(&branch.id)?;
+        Ok(true)
+    }
+
+    /// Make the most common branch image the project image once every branch
+    /// runs it, so new branches start on it too.
+    fn normalize_images(&self, project: &model::Project) -> Result<()> {
+        let store = self.store();
+        let branches = store.list_branches(&project.id)?;
+        let mut images = branches
+            .iter()
+            .map(|b| b.image.as_deref().unwrap_or(&project.image));
+        let Some(first) = images.next() else {
+            return Ok(());
+        };
+        if images.all(|image| image == first) {
+            store.update_project_image(&project.id, first)?;
+            for branch in &branches {
+                if branch.image.is_some() {
+                    store.update_branch_image(&branch.id, None)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Create the configured extensions in a running branch.
+    async fn ensure_extensions(&self, container_name: &str) -> Result<()> {
+        for configured in &self.extensions {
+            let name = image::extension_name(configured);
+            self.runtime
+                .psql(
+                    container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    &format!(
+                        "CREATE EXTENSION IF NOT EXISTS \"{}\" CASCADE",
+                        name.replace('"', "\"\"")
+                    ),
+                )
+                .await
+                .with_context(|| format!("failed to create extension '{}'", name))?;
+        }
+        Ok(())
+    }
+
+    /// Compare the configured init scripts with the ones that ran when the
+    /// project's first branch was created.
+    fn init_scripts_check(&self, dir: &std::path::Path) -> DoctorCheck {
+        let name = "Init scripts".to_string();
+        let current = match init_scripts::checksums(dir) {
+            Ok(current) => current,
+            Err(e) => {
+                return DoctorCheck {
+                    name,
+                    available: false,
+                    detail: format!("{:#}", e),
+                }
+            }
+        };
+
+        let recorded = {
+            let store = self.store();
+            store
+                .get_project_by_name(&self.project_name)
+                .and_then(|project| match project {
+                    Some(project) => store.list_init_scripts(&project.id),
+                    None => Ok(Default::default()),
+                })
+        };
+        let recorded = match recorded {
+            Ok(recorded) => recorded,
+            Err(e) => {
+                return DoctorCheck {
+                    name,
+                    available: false,
+                    detail: format!("{:#}", e),
+                }
+            }
+        };
+
+        if recorded.is_empty() {
+            return DoctorCheck {
+                name,
+                available: true,
+                detail: format!(
+                    "{} script(s) in {}; not applied yet",
+                    current.len(),
+                    dir.display()
+                ),
+            };
+        }
+
+        let changes = init_scripts::changes(&recorded, &current);
+        if changes.is_empty() {
+            DoctorCheck {
+                name,
+                available: true,
+                detail: format!(
+                    "{} script(s) unchanged since initialization",
+                    recorded.len()
+                ),
+            }
+        } else {
+            DoctorCheck {
+                name,
+                available: false,
+                detail: format!(
+                    "Changed since the project was initialized: {}. Existing branches are not affected; recreate main to apply them",
+                    changes.join(", ")
+                ),
+            }
+        }
+    }
+
+    /// Container settings for a branch, including its effective server settings.
+    fn container_options_for(&self, branch: &model::Branch) -> Result<ContainerOptions> {
+        self.container_options_with(branch, self.unix_sockets)
+    }
+
+    /// Like [`Self::container_options_for`], with the branch's socket directory
+    /// mounted only when `expose_socket` is set. A second container for the
+    /// same branch must not take over its socket.
+    fn container_options_with(
+        &self,
+        branch: &model::Branch,
+        expose_socket: bool,
+    ) -> Result<ContainerOptions> {
+        let mut options = self
+            .branch_container_options
+            .get(&branch.name)
+            .unwrap_or(&self.container_options)
+            .clone();
+        options.bind_address = Some(self.bind_address.clone());
+        // The subscription reaches a localhost upstream through the host gateway
+        if self.sync_config_for(&branch.name).is_some() {
+            options.extra_hosts.push(seed::HOST_GATEWAY.to_string());
+        }
+        if self.tls {
+            let dir = self.tls_dir(&branch.project_id);
+            let mut hosts = vec![
+                "localhost".to_string(),
+                "127.0.0.1".to_string(),
+                "::1".to_string(),
+            ];
+            let host = self.connection_host();
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+            tls::ensure(&dir, &self.project_name, &hosts)?;
+            options
+                .mounts
+                .push(format!("{}:{}:ro", dir.display(), tls::CONTAINER_DIR));
+            options.files.push(docker::ContainerFile {
+                path: tls::KEY_CONTAINER_PATH.to_string(),
+                contents: tls::server_key(&dir)?,
+                mode: 0o600,
+            });
+        }
+        if expose_socket {
+            let dir = self.socket_dir(&branch.name);
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("failed to create socket directory {}", dir.display()))?;
+            // Socket connections are trusted, so keep them to the current user
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+            }
+            if let Some(socket_path_len) = socket_path_too_long(&dir) {
+                log::warn!(
+                    "Socket path for branch '{}' is {} bytes, longer than most clients accept; use a shorter data_root",
+                    branch.name,
+                    socket_path_len
+                );
+            }
+            options
+                .mounts
+                .push(format!("{}:{}", dir.display(), SOCKET_CONTAINER_DIR));
+        }
+
+        // A fresh cluster runs the init scripts once, during initdb. Root
+        // branches keep the mount so their container spec stays the same.
+        if let (None, Some(dir)) = (&branch.parent_branch_id, &self.init_scripts) {
+            options
+                .mounts
+                .push(format!("{}:{}:ro", dir.display(), init_scripts::INITDB_DIR));
+        }
+
+        let dir = self.write_branch_settings(branch)?;
+        options
+            .mounts
+            .push(format!("{}:{}:ro", dir.display(), settings::CONTAINER_DIR));
+        options.postgres_settings = vec![settings::config_file_setting()];
+        Ok(options)
+    }
+
+    /// Write the branch's effective server settings to the config file its
+    /// container includes, returning the directory holding it.
+    fn write_branch_settings(&self, branch: &model::Branch) -> Result<PathBuf> {
+        let mut server_settings: Vec<_> = self
+            .effective_settings(branch)?
+            .into_iter()
+            .map(|s| (s.name, s.value))
+            .collect();
+        self.add_preload_libraries(&mut server_settings);
+        if self.tls {
+            server_settings.extend(tls::server_settings());
+        }
+        let dir = self.settings_dir(branch);
+        settings::write_config(&dir, &server_settings)?;
+        Ok(dir)
+    }
+
+    /// Preload the libraries configured extensions need, keeping any the
+    /// user already lists.
+    fn add_preload_libraries(&self, server_settings: &mut Vec<(String, String)>) {
+        let needed = image::preload_libraries(&self.extensions);
+        if needed.is_empty() {
+            return;
+        }
+        let position = server_settings
+            .iter()
+            .position(|(name, _)| name == "shared_preload_libraries");
+        let mut libraries: Vec<String> = position
+            .map(|i| {
+                server_settings[i]
+                    .1
+                    .split(',')
+                    .map(|l| l.trim().to_string())
+                    .filter(|l| !l.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        for library in &needed {
+            if !libraries.iter().any(|l| l == library) {
+                libraries.push(library.to_string());
+            }
+        }
+        let value = libraries.join(",");
+        match position {
+            Some(i) => server_settings[i].1 = value,
+            None => server_settings.push(("shared_preload_libraries".to_string(), value)),
+        }
+        if needed.contains(&"pg_cron")
+            && !server_settings
+                .iter()
+                .any(|(name, _)| name == "cron.database_name")
+        {
+            server_settings.push(("cron.database_name".to_string(), self.pg_db.clone()));
+        }
+    }
+
+    /// Project-wide `postgres_settings` with the branch's overrides applied.
+    fn effective_settings(&self, branch: &model::Branch) -> Result<Vec<PostgresSetting>> {
+        let mut settings: std::collections::BTreeMap<String, PostgresSetting> = self
+            .postgres_settings
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.clone(),
+                    PostgresSetting {
+                        name: name.clone(),
+                        value: value.clone(),
+                        source: "project".to_string(),
+                    },
+                )
+            })
+            .collect();
+        for (name, value) in self.store().list_branch_settings(&branch.id)? {
+            settings.insert(
+                name.clone(),
+                PostgresSetting {
+                    name,
+                    value,
+                    source: "branch".to_string(),
+                },
+            );
+        }
+        Ok(settings.into_values().collect())
+    }
+
+    /// Apply changed settings `names` to a running branch: reload its
+    /// configuration, or restart it when one of them needs a restart.
+    async fn apply_settings(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+        names: &[String],
+    ) -> Result<SettingsApplied> {
+        self.write_branch_settings(branch)?;
+        if self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            != docker::ContainerStatus::Running
+        {
+            return Ok(SettingsApplied::NextStart);
+        }
+
+        if self.settings_need_restart(branch, names).await? {
+            self.runtime.stop_branch(&branch.container_name).await?;
+            self.wake_branch(project, branch).await?;
+            return Ok(SettingsApplied::Restarted);
+        }
+
+        let errors = self
+            .runtime
+            .psql(
+                &branch.container_name,
+                &self.pg_user,
+                &self.pg_db,
+                &format!(
+                    "SELECT name || ': ' || error FROM pg_file_settings \
+                     WHERE error IS NOT NULL AND name IN ({})",
+                    sql_names(names)
+                ),
+            )
+            .await?;
+        if !errors.trim().is_empty() {
+            anyhow::bail!("Invalid settings: {}", errors.trim().replace('\n', "; "));
+        }
+        self.runtime
+            .psql(
+                &branch.container_name,
+                &self.pg_user,
+                &self.pg_db,
+                "SELECT pg_reload_conf()",
+            )
+            .await?;
+        Ok(SettingsApplied::Reloaded)
+    }
+
+    /// Whether the running server can only pick up settings `names` with a
+    /// restart: they include postmaster-level or unknown settings, or it was
+    /// started with its settings on the command line.
+    async fn settings_need_restart(
+        &self,
+        branch: &model::Branch,
+        names: &[String],
+    ) -> Result<bool> {
+        let config_file = self
+            .runtime
+            .psql(
+                &branch.container_name,
+                &self.pg_user,
+                &self.pg_db,
+                "SHOW config_file",
+            )
+            .await?;
+        if config_file.trim() != settings::config_file_setting().1 {
+            return Ok(true);
+        }
+
+        let contexts = self
+            .runtime
+            .psql(
+                &branch.container_name,
+                &self.pg_user,
+                &self.pg_db,
+                &format!(
+                    "SELECT name, context FROM pg_settings WHERE name IN ({})",
+                    sql_names(names)
+                ),
+            )
+            .await?;
+        let contexts: HashMap<&str, &str> = contexts
+            .lines()
+            .filter_map(|line| line.split_once('|'))
+            .collect();
+        Ok(names.iter().any(|name| {
+            !matches!(
+                contexts.get(name.as_str()),
+                Some(&("sighup" | "superuser" | "user"))
+            )
+        }))
+    }
+
+    /// Put back a branch's previous settings after applying new ones failed.
+    async fn restore_settings(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+        previous: &[(String, String)],
+        was_running: bool,
+    ) -> Result<()> {
+        self.store().replace_branch_settings(&branch.id, previous)?;
+        self.write_branch_settings(branch)?;
+        if !was_running {
+            return Ok(());
+        }
+        if self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            == docker::ContainerStatus::Running
+        {
+            self.runtime
+                .psql(
+                    &branch.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    "SELECT pg_reload_conf()",
+                )
+                .await?;
+        } else {
+            self.wake_branch(project, branch).await?;
+        }
+        Ok(())
+    }
+
+    /// Apply a change to a branch's stored settings, rolling it back if the
+    /// branch cannot pick it up.
+    async fn change_settings(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+        previous: Vec<(String, String)>,
+        names: &[String],
+    ) -> Result<SettingsApplied> {
+        let was_running = branch.state == BranchState::Running;
+        let err = match self.apply_settings(project, branch, names).await {
+            Ok(applied) => return Ok(applied),
+            Err(err) => err,
+        };
+        if let Err(restore_err) = self
+            .restore_settings(project, branch, &previous, was_running)
+            .await
+        {
+            log::warn!(
+                "Failed to restore the previous settings of '{}': {:#}",
+                branch.name,
+                restore_err
+            );
+        }
+        Err(err.context(format!(
+            "failed to apply settings to '{}'; the previous settings were restored",
+            branch.name
+        )))
+    }
+
+    /// Start a stopped branch container, wait for readiness and mark it used.
+    /// Returns the port it runs on.
+    async fn wake_branch(&self, project: &model::Project, branch: &model::Branch) -> Result<u16> {
+        let spec = self.start_spec(project, branch).await?;
+        let port = spec.port;
+        self.runtime.start_branch(&spec).await?;
+
+        self.runtime
             .wait_ready(
                 &branch.container_name,
                 &self.pg_user,
@@ -448,191 +1593,1411 @@ impl DatabaseBranchingBackend for LocalBackend {
                 STARTUP_TIMEOUT,
             )
             .await?;
-        self.store()
-            .update_branch_state(&branch.id, BranchState::Running)?;
+        self.ensure_extensions(&branch.container_name).await?;
 
-        Ok(())
+        let store = self.store();
+        store.update_branch_state(&branch.id, BranchState::Running)?;
+        store.touch_branch(&branch.id)?;
+        Ok(port)
+    }
+
+    /// What to start a branch's container with. Prepared one operation at a
+    /// time, as it picks ports and may build images and certificates.
+    async fn start_spec(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+    ) -> Result<StartBranchSpec> {
+        let _setup = self.setup_lock.lock().await;
+        Ok(StartBranchSpec {
+            port: self.ensure_port(branch).await?,
+            image: self.runtime_image(project, branch).await?,
+            container_name: branch.container_name.clone(),
+            data_dir: PathBuf::from(&branch.data_dir),
+            pg_user: self.pg_user.clone(),
+            pg_password: self.password(project),
+            pg_db: self.pg_db.clone(),
+            options: self.container_options_for(branch)?,
+        })
+    }
+
+    /// Pick a port for a new branch: a hash of the project and branch names
+    /// within the port range, or the next free port after it.
+    async fn assign_port(&self, branch_name: &str) -> Result<u16> {
+        let reserved = self.store().used_ports()?;
+        let preferred = hashed_port(
+            &self.project_name,
+            branch_name,
+            self.port_range_start,
+            self.port_range_end,
+        );
+        docker::pick_available_port(
+            self.runtime.client(),
+            preferred,
+            self.port_range_start..=self.port_range_end,
+            &reserved,
+        )
+        .await
+    }
+
+    /// The port to start a branch on. If another process took the branch's
+    /// port while its container was down, the branch moves to a new one and
+    /// its container is recreated; pinned ports are never moved.
+    async fn ensure_port(&self, branch: &model::Branch) -> Result<u16> {
+        if self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            == docker::ContainerStatus::Running
+            || docker::port_available(self.runtime.client(), branch.port).await
+        {
+            return Ok(branch.port);
+        }
+        if branch.port_pinned {
+            anyhow::bail!(
+                "Port {} pinned for branch '{}' is in use by another process",
+                branch.port,
+                branch.name
+            );
+        }
+
+        let port = self.assign_port(&branch.name).await?;
+        self.store().update_branch_port(&branch.id, port, false)?;
+        log::info!(
+            "Port {} of branch '{}' is in use; moved it to port {}",
+            branch.port,
+            branch.name,
+            port
+        );
+        self.port_reassignments
+            .lock()
+            .unwrap()
+            .push(PortReassignment {
+                branch: branch.name.clone(),
+                old_port: branch.port,
+                new_port: port,
+            });
+        Ok(port)
+    }
+
+    /// Stop running branches that have been unused for longer than
+    /// `idle_timeout` and have no client connections.
+    async fn stop_idle(&self, project: &model::Project) -> Result<Vec<String>> {
+        let Some(timeout) = self.idle_timeout else {
+            return Ok(vec![]);
+        };
+
+        let cutoff = Utc::now().timestamp_millis() - timeout.as_millis() as i64;
+        let candidates = idle_candidates(
+            self.store().list_branches(&project.id)?,
+            cutoff,
+            self.replication.as_ref().map(|repl| repl.branch.as_str()),
+        );
+
+        let mut stopped = Vec::new();
+        for branch in candidates {
+            let connections = self
+                .runtime
+                .psql(
+                    &branch.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    "SELECT count(*) FROM pg_stat_activity \
+                     WHERE backend_type = 'client backend' AND pid <> pg_backend_pid()",
+                )
+                .await;
+
+            match connections.as_deref().map(str::trim) {
+                Ok("0") => {}
+                Ok(_) => {
+                    // Still in use: restart the idle clock
+                    self.store().touch_branch(&branch.id)?;
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Failed to check activity on branch '{}': {}",
+                        branch.name,
+                        e
+                    );
+                    continue;
+                }
+            }
+
+            if let Err(e) = self.runtime.stop_branch(&branch.container_name).await {
+                log::warn!("Failed to stop idle branch '{}': {:#}", branch.name, e);
+                continue;
+            }
+            self.store()
+                .update_branch_state(&branch.id, BranchState::IdleStopped)?;
+            log::info!("Stopped idle branch '{}'", branch.name);
+            stopped.push(branch.name);
+        }
+
+        Ok(stopped)
+    }
+
+    /// Sweep idle branches at least once a minute until none is left running that
+    /// could go idle. Only one watcher runs per project; others return at once.
+    async fn watch_idle(&self) -> Result<()> {
+        let Some(timeout) = self.idle_timeout else {
+            return Ok(());
+        };
+        let watch_name = format!("{}.idle-watch", self.project_name);
+        let Some(watch) = ProjectLock::try_acquire(&self.data_root.join("locks"), &watch_name)?
+        else {
+            return Ok(());
+        };
+
+        let interval = (timeout / 4).clamp(Duration::from_secs(5), Duration::from_secs(60));
+        loop {
+            tokio::time::sleep(interval).await;
+            let project = self.ensure_project().await?;
+            let _lock = self.lock_project().await?;
+            self.reconcile_project(&project).await?;
+            self.stop_idle(&project).await?;
+
+            // Released under the project lock, so a branch started after this
+            // check can spawn a new watcher
+            let running = idle_candidates(
+                self.store().list_branches(&project.id)?,
+                i64::MAX,
+                self.replication.as_ref().map(|repl| repl.branch.as_str()),
+            );
+            if running.is_empty() {
+                drop(watch);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Restore a seed source into a running container, then apply the
+    /// configured post-seed scripts and verification queries.
+    ///
+    /// When the branch follows an upstream through logical replication, only
+    /// the schema is restored and the subscription performs the data copy.
+    async fn run_seed(
+        &self,
+        branch_name: &str,
+        container_name: &str,
+        source: &seed::SeedSource,
+        slot_suffix: &str,
+    ) -> Result<()> {
+        match (self.sync_config_for(branch_name), source) {
+            (Some(repl), seed::SeedSource::PostgresUrl(url)) => {
+                seed::seed_schema_from_postgres(
+                    self.runtime.client(),
+                    url,
+                    container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    &self.image,
+                )
+                .await?;
+
+                let target = self.sync_target(container_name);
+                replication::create_subscription(&target, repl, slot_suffix).await?;
+                replication::wait_initial_sync(&target, &repl.subscription).await?;
+            }
+            (repl, _) => {
+                if repl.is_some() {
+                    log::warn!(
+                        "Replication is configured for '{}' but the seed source is not a PostgreSQL URL; skipping subscription",
+                        branch_name
+                    );
+                }
+                seed::seed_branch(
+                    self.runtime.client(),
+                    source,
+                    container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    &self.image,
+                )
+                .await?;
+            }
+        }
+
+        seed::run_post_seed_scripts(
+            self.runtime.client(),
+            container_name,
+            &self.pg_user,
+            &self.pg_db,
+            &self.seed_config.scripts,
+            &self.config_dir,
+        )
+        .await?;
+
+        seed::verify_seed(
+            self.runtime.client(),
+            container_name,
+            &self.pg_user,
+            &self.pg_db,
+            &self.seed_config.verify,
+        )
+        .await
+    }
+
+    /// Replication settings, if `branch_name` is the branch following the upstream.
+    fn sync_config_for(&self, branch_name: &str) -> Option<&ReplicationConfig> {
+        self.replication
+            .as_ref()
+            .filter(|repl| repl.branch == branch_name)
+    }
+
+    fn sync_target<'a>(&'a self, container_name: &'a str) -> replication::SyncTarget<'a> {
+        replication::SyncTarget {
+            runtime: &self.runtime,
+            container_name,
+            pg_user: &self.pg_user,
+            pg_db: &self.pg_db,
+        }
+    }
+
+    /// Disable apply on the sync branch so a clone does not inherit an active
+    /// subscription. The branch is started if needed, since a stopped parent
+    /// would otherwise hand its enabled subscription (and slot) to the child;
+    /// [`end_sync_pause`](Self::end_sync_pause) stops it again.
+    async fn pause_sync_for_clone(
+        &self,
+        project: &model::Project,
+        parent: &model::Branch,
+    ) -> Result<SyncPause> {
+        let mut pause = SyncPause::default();
+        let Some(repl) = self.sync_config_for(&parent.name) else {
+            return Ok(pause);
+        };
+
+        if self
+            .runtime
+            .container_status(&parent.container_name)
+            .await?
+            != docker::ContainerStatus::Running
+        {
+            pause.started = true;
+            self.runtime
+                .start_branch(&StartBranchSpec {
+                    image: self.runtime_image(project, parent).await?,
+                    container_name: parent.container_name.clone(),
+                    data_dir: PathBuf::from(&parent.data_dir),
+                    port: self.ensure_port(parent).await?,
+                    pg_user: self.pg_user.clone(),
+                    pg_password: self.password(project),
+                    pg_db: self.pg_db.clone(),
+                    options: self.container_options_for(parent)?,
+                })
+                .await?;
+            self.runtime
+                .wait_ready(
+                    &parent.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    STARTUP_TIMEOUT,
+                )
+                .await?;
+            self.store()
+                .update_branch_state(&parent.id, BranchState::Running)?;
+        }
+
+        match replication::set_enabled(
+            &self.sync_target(&parent.container_name),
+            &repl.subscription,
+            false,
+        )
+        .await
+        {
+            Ok(paused) => {
+                pause.resumable = paused;
+                Ok(pause)
+            }
+            Err(err) => {
+                self.end_sync_pause(parent, pause).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Resume the sync branch after a clone and stop it again if
+    /// [`pause_sync_for_clone`](Self::pause_sync_for_clone) started it.
+    async fn end_sync_pause(&self, parent: &model::Branch, pause: SyncPause) {
+        if pause.resumable {
+            self.resume_sync(parent).await;
+        }
+        if pause.started {
+            let stopped = match self.runtime.stop_branch(&parent.container_name).await {
+                Ok(()) => self.store().update_branch_state(&parent.id, parent.state),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = stopped {
+                log::warn!(
+                    "Failed to stop '{}' again after cloning it: {:#}",
+                    parent.name,
+                    err
+                );
+            }
+        }
+    }
+
+    async fn resume_sync(&self, parent: &model::Branch) {
+        let Some(repl) = self.sync_config_for(&parent.name) else {
+            return;
+        };
+        if let Err(e) = replication::set_enabled(
+            &self.sync_target(&parent.container_name),
+            &repl.subscription,
+            true,
+        )
+        .await
+        {
+            log::warn!(
+                "Failed to resume sync on '{}': {}; run 'ALTER SUBSCRIPTION {} ENABLE' manually",
+                parent.name,
+                e,
+                repl.subscription
+            );
+        }
+    }
+
+    /// Remove the subscription a clone of `parent` inherited from it, if
+    /// `parent` is the sync branch.
+    async fn detach_inherited_sync(
+        &self,
+        parent: &model::Branch,
+        container_name: &str,
+    ) -> Result<()> {
+        if let Some(repl) = self.sync_config_for(&parent.name) {
+            replication::detach_clone(&self.sync_target(container_name), &repl.subscription)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Best-effort removal of the sync branch's subscription so its upstream
+    /// replication slot does not keep retaining WAL after the branch is gone.
+    /// Recreate a branch's container on refreshed data under the same name
+    /// and port, putting the previous data back if it fails to come up.
+    /// Child branches keep their own copies, or on ZFS clones, of the old data.
+    async fn swap_refreshed_data(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+        staging: &model::Branch,
+    ) -> Result<()> {
+        self.drop_sync(branch).await;
+        self.runtime.remove_branch(&branch.container_name).await?;
+        self.store().update_branch_data(
+            &branch.id,
+            &staging.data_dir,
+            staging.storage_metadata.as_deref(),
+        )?;
+
+        let started = async {
+            self.runtime
+                .start_branch(&StartBranchSpec {
+                    image: self.runtime_image(project, branch).await?,
+                    container_name: branch.container_name.clone(),
+                    data_dir: PathBuf::from(&staging.data_dir),
+                    port: self.ensure_port(branch).await?,
+                    pg_user: self.pg_user.clone(),
+                    pg_password: self.password(project),
+                    pg_db: self.pg_db.clone(),
+                    options: self.container_options_for(branch)?,
+                })
+                .await?;
+            self.runtime
+                .wait_ready(
+                    &branch.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    STARTUP_TIMEOUT,
+                )
+                .await
+        }
+        .await;
+
+        if let Err(err) = started {
+            let _ = self.runtime.remove_branch(&branch.container_name).await;
+            self.store().update_branch_data(
+                &branch.id,
+                &branch.data_dir,
+                branch.storage_metadata.as_deref(),
+            )?;
+            if let Err(e) = self.storage.delete_branch_data(project, staging).await {
+                log::warn!("Failed to remove refreshed data after rollback: {}", e);
+            }
+            return Err(err.context(format!(
+                "refreshed data for '{}' failed to start; rolled back",
+                branch.name
+            )));
+        }
+        Ok(())
+    }
+
+    async fn drop_sync(&self, branch: &model::Branch) {
+        let Some(repl) = self.sync_config_for(&branch.name) else {
+            return;
+        };
+        let running = matches!(
+            self.runtime.container_status(&branch.container_name).await,
+            Ok(docker::ContainerStatus::Running)
+        );
+        let result = if running {
+            replication::drop_subscription(
+                &self.sync_target(&branch.container_name),
+                &repl.subscription,
+            )
+            .await
+        } else {
+            Err(anyhow::anyhow!("container is not running"))
+        };
+        if let Err(e) = result {
+            log::warn!(
+                "Could not drop subscription '{}' on '{}': {}; its upstream replication slot may need manual cleanup",
+                repl.subscription,
+                branch.name,
+                e
+            );
+        }
+    }
+
+    fn connection_uri(&self, project: &model::Project, port: u16) -> String {
+        let uri = format!(
+            "postgresql://{}:{}@{}:{}/{}",
+            self.pg_user,
+            self.password(project),
+            self.connection_host(),
+            port,
+            self.pg_db
+        );
+        match self.tls_params(project) {
+            Some((sslmode, sslrootcert)) => format!(
+                "{}?sslmode={}&sslrootcert={}",
+                uri,
+                sslmode,
+                url::form_urlencoded::byte_serialize(sslrootcert.as_bytes()).collect::<String>()
+            ),
+            None => uri,
+        }
+    }
+
+    /// `sslmode` and `sslrootcert` for clients when TLS is on.
+    fn tls_params(&self, project: &model::Project) -> Option<(String, String)> {
+        self.tls.then(|| {
+            (
+                "verify-full".to_string(),
+                tls::ca_cert_path(&self.tls_dir(&project.id))
+                    .to_string_lossy()
+                    .to_string(),
+            )
+        })
+    }
+}
+
+#[async_trait]
+impl DatabaseBranchingBackend for LocalBackend {
+    async fn create_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<BranchInfo> {
+        self.create_branch_on(branch_name, from_branch, &CreateBranchOptions::default())
+            .await
+    }
+
+    async fn create_branch_with_options(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+        options: &CreateBranchOptions,
+    ) -> Result<BranchInfo> {
+        self.create_branch_on(branch_name, from_branch, options)
+            .await
+    }
+
+    async fn delete_branch(&self, branch_name: &str) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+
+        self.remove_branch(&project, &branch).await
+    }
+
+    async fn list_branches(&self) -> Result<Vec<BranchInfo>> {
+        let project = self.ensure_project().await?;
+        self.reconcile_project(&project).await?;
+
+        let all_branches = self.store().list_branches(&project.id)?;
+
+        // Build id→name map so we can resolve parent_branch_id to a name
+        let id_to_name: std::collections::HashMap<&str, &str> = all_branches
+            .iter()
+            .map(|b| (b.id.as_str(), b.name.as_str()))
+            .collect();
+        let branches: Vec<_> = all_branches
+            .iter()
+            .filter(|b| !pool::is_pool_branch(&b.name))
+            .collect();
+
+        Ok(branches
+            .iter()
+            .map(|b| {
+                let parent = b
+                    .parent_branch_id
+                    .as_deref()
+                    .and_then(|pid| id_to_name.get(pid))
+                    .map(|name| name.to_string());
+                self.branch_info(b, parent)
+            })
+            .collect())
+    }
+
+    async fn branch_sizes(&self) -> Result<std::collections::HashMap<String, u64>> {
+        let project = self.ensure_project().await?;
+        let branches = self.store().list_branches(&project.id)?;
+        let mut sizes = std::collections::HashMap::new();
+        for branch in branches {
+            if pool::is_pool_branch(&branch.name) {
+                continue;
+            }
+            match self.storage.branch_size(&project, &branch).await {
+                Ok(Some(size)) => {
+                    sizes.insert(branch.name, size);
+                }
+                Ok(None) => {}
+                Err(err) => log::debug!("Failed to measure branch '{}': {:#}", branch.name, err),
+            }
+        }
+        Ok(sizes)
+    }
+
+    async fn branch_exists(&self, branch_name: &str) -> Result<bool> {
+        let project = self.ensure_project().await?;
+        Ok(self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .is_some())
+    }
+
+    async fn switch_to_branch(&self, branch_name: &str) -> Result<BranchInfo> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        self.reconcile_project(&project).await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+
+        // Start if stopped
+        if matches!(
+            branch.state,
+            BranchState::Stopped | BranchState::IdleStopped
+        ) {
+            self.wake_branch(&project, &branch).await?;
+        } else {
+            self.store().touch_branch(&branch.id)?;
+        }
+
+        // Switching is frequent enough to sweep the branches left behind
+        if let Err(e) = self.stop_idle(&project).await {
+            log::warn!("Failed to stop idle branches: {:#}", e);
+        }
+
+        let parent = self.parent_name(&project, &branch)?;
+        // Re-read: waking may have moved the branch to another port
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .unwrap_or(branch);
+        Ok(BranchInfo {
+            state: Some("running".to_string()),
+            ..self.branch_info(&branch, parent)
+        })
+    }
+
+    async fn get_connection_info(&self, branch_name: &str) -> Result<ConnectionInfo> {
+        let project = self.ensure_project().await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+
+        let port = if branch.state == BranchState::IdleStopped {
+            let _lock = self.lock_project().await?;
+            // Another process may have woken or removed it while we waited
+            let branch = self
+                .store()
+                .get_branch_by_name(&project.id, branch_name)?
+                .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            log::info!("Starting idle-stopped branch '{}'", branch.name);
+            self.wake_branch(&project, &branch).await?
+        } else {
+            self.store().touch_branch(&branch.id)?;
+            branch.port
+        };
+
+        let tls = self.tls_params(&project);
+        Ok(ConnectionInfo {
+            host: self.connection_host(),
+            port,
+            database: self.pg_db.clone(),
+            user: self.pg_user.clone(),
+            password: Some(self.password(&project)),
+            connection_string: Some(self.connection_uri(&project, port)),
+            sslmode: tls.as_ref().map(|(mode, _)| mode.clone()),
+            sslrootcert: tls.map(|(_, cert)| cert),
+            socket_dir: self
+                .unix_sockets
+                .then(|| self.socket_dir(&branch.name).to_string_lossy().to_string()),
+        })
+    }
+
+    async fn resource_usage(&self) -> Result<HashMap<String, ResourceUsage>> {
+        let project = self.ensure_project().await?;
+        let running: Vec<_> = self
+            .store()
+            .list_branches(&project.id)?
+            .into_iter()
+            .filter(|branch| branch.state == BranchState::Running)
+            .filter(|branch| !pool::is_pool_branch(&branch.name))
+            .collect();
+
+        // Each sample takes about a second, so take them all at once
+        let samples = futures_util::future::join_all(
+            running
+                .iter()
+                .map(|branch| self.runtime.container_stats(&branch.container_name)),
+        )
+        .await;
+
+        let mut usage = HashMap::new();
+        for (branch, sample) in running.into_iter().zip(samples) {
+            match sample {
+                Ok(stats) => {
+                    usage.insert(
+                        branch.name,
+                        ResourceUsage {
+                            cpu_percent: stats.cpu_percent,
+                            memory_bytes: stats.memory_bytes,
+                            memory_limit_bytes: stats.memory_limit_bytes,
+                        },
+                    );
+                }
+                Err(err) => log::debug!("No stats for branch '{}': {:#}", branch.name, err),
+            }
+        }
+        Ok(usage)
+    }
+
+    async fn repair_plans(&self, branch_name: Option<&str>) -> Result<Vec<RepairPlan>> {
+        let project = self.ensure_project().await?;
+        self.reconcile_project(&project).await?;
+        self.repair_plans_for(&project, branch_name).await
+    }
+
+    async fn repair_branch(&self, branch_name: &str, action: RepairAction) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        self.apply_repair(&project, branch_name, action).await
+    }
+
+    async fn branch_labels(&self) -> Result<HashMap<String, HashMap<String, String>>> {
+        let project = self.ensure_project().await?;
+        let branches = self.store().list_branches(&project.id)?;
+        Ok(branches
+            .iter()
+            .filter(|b| !pool::is_pool_branch(&b.name))
+            .map(|b| {
+                let labels = self
+                    .branch_container_options
+                    .get(&b.name)
+                    .unwrap_or(&self.container_options)
+                    .labels
+                    .clone();
+                (b.name.clone(), labels)
+            })
+            .collect())
+    }
+
+    async fn record_event(&self, event: &BranchEvent) -> Result<()> {
+        self.store().insert_event(&self.project_name, event)
+    }
+
+    async fn list_events(
+        &self,
+        _database: &str,
+        branch_name: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<BranchEvent>> {
+        self.store()
+            .list_events(&self.project_name, branch_name, limit)
+    }
+
+    async fn branch_container(&self, branch_name: &str) -> Result<Option<String>> {
+        let project = self.ensure_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+        Ok(Some(branch.container_name))
+    }
+
+    async fn start_branch(&self, branch_name: &str) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+
+        self.wake_branch(&project, &branch).await?;
+        Ok(())
+    }
+
+    async fn stop_branch(&self, branch_name: &str) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+
+        self.runtime.stop_branch(&branch.container_name).await?;
+        self.store()
+            .update_branch_state(&branch.id, BranchState::Stopped)?;
+
+        Ok(())
+    }
+
+    async fn reset_branch(&self, branch_name: &str) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+
+        let was_running = branch.state == BranchState::Running;
+
+        // Stop container
+        self.runtime.stop_branch(&branch.container_name).await?;
+
+        // Re-clone from parent if available
+        let parent = match &branch.parent_branch_id {
+            Some(parent_id) => self
+                .store()
+                .list_branches(&project.id)?
+                .into_iter()
+                .find(|b| &b.id == parent_id),
+            None => None,
+        };
+        if let Some(parent_branch) = &parent {
+            let majors = (
+                upgrade::data_dir_major(Path::new(&branch.data_dir)),
+                upgrade::data_dir_major(Path::new(&parent_branch.data_dir)),
+            );
+            if let (Ok(major), Ok(parent_major)) = majors {
+                if major != parent_major {
+                    anyhow::bail!(
+                        "Branch '{}' runs PostgreSQL {} but its parent '{}' runs {}; delete and recreate it instead",
+                        branch.name,
+                        major,
+                        parent_branch.name,
+                        parent_major
+                    );
+                }
+            }
+
+            let data_dir = PathBuf::from(&branch.data_dir);
+            let new_metadata = {
+                let _clone = self.clone_lock.lock().await;
+                self.clone_parent_data(&project, parent_branch, &branch.id, &data_dir, None)
+                    .await?
+            };
+
+            let store = self.store();
+            if let Some(metadata) = &new_metadata {
+                store.update_branch_storage_metadata(&branch.id, Some(metadata))?;
+            }
+            store.mark_branch_data_changed(&branch.id)?;
+        }
+
+        // Restart if it was running
+        if was_running {
+            let spec = self.start_spec(&project, &branch).await?;
+            self.runtime.start_branch(&spec).await?;
+
+            self.runtime
+                .wait_ready(
+                    &branch.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    STARTUP_TIMEOUT,
+                )
+                .await?;
+            if let Some(parent_branch) = &parent {
+                self.detach_inherited_sync(parent_branch, &branch.container_name)
+                    .await?;
+            }
+            self.store()
+                .update_branch_state(&branch.id, BranchState::Running)?;
+        } else {
+            self.store()
+                .update_branch_state(&branch.id, BranchState::Stopped)?;
+        }
+
+        Ok(())
+    }
+
+    fn supports_lifecycle(&self) -> bool {
+        true
+    }
+
+    async fn test_connection(&self) -> Result<()> {
+        let doctor = self.runtime.doctor().await;
+        if !doctor.available {
+            anyhow::bail!("Docker is not available: {}", doctor.detail);
+        }
+        Ok(())
+    }
+
+    async fn doctor(&self) -> Result<DoctorReport> {
+        let mut checks = vec![];
+
+        // Docker check
+        let docker_result = self.runtime.doctor().await;
+        checks.push(DoctorCheck {
+            name: "Docker".to_string(),
+            available: docker_result.available,
+            detail: if let Some(version) = docker_result.version {
+                format!("Docker {} available", version)
+            } else {
+                docker_result.detail
+            },
+        });
+
+        // Storage check
+        let storage_report = self.storage.doctor().await;
+        for entry in &storage_report.entries {
+            if entry.available || entry.selected {
+                checks.push(DoctorCheck {
+                    name: format!("Storage: {}", entry.kind),
+                    available: entry.available,
+                    detail: entry.detail.clone(),
+                });
+            }
+        }
+
+        checks.push(DoctorCheck {
+            name: "Default storage".to_string(),
+            available: true,
+            detail: format!(
+                "Using {} for new projects",
+                storage_report.default_backend.as_str()
+            ),
+        });
+
+        // State database
+        let schema_version = self.store().schema_version();
+        checks.push(DoctorCheck {
+            name: "State database".to_string(),
+            available: schema_version.is_ok(),
+            detail: match schema_version {
+                Ok(version) => format!(
+                    "{}/state.db (schema v{}, latest v{})",
+                    self.data_root.display(),
+                    version,
+                    migrations::LATEST_VERSION
+                ),
+                Err(err) => format!("{}/state.db: {:#}", self.data_root.display(), err),
+            },
+        });
+
+        if let Some(ref dir) = self.init_scripts {
+            checks.push(self.init_scripts_check(dir));
+        }
+
+        Ok(DoctorReport { checks })
+    }
+
+    async fn init_project(&self, _project_name: &str) -> Result<()> {
+        let _project = self.ensure_project().await?;
+        Ok(())
+    }
+
+    async fn seed_from_source(&self, branch_name: &str, source: &str) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+        let parsed = seed::parse_source(source)?;
+
+        // Record the source so `refresh` can replay it later from any directory
+        let recorded_source = match &parsed {
+            seed::SeedSource::LocalFile(path) => std::path::absolute(path)
+                .unwrap_or_else(|_| path.clone())
+                .to_string_lossy()
+                .to_string(),
+            _ => source.to_string(),
+        };
+        {
+            let store = self.store();
+            store.update_project_seed_source(&project.id, &recorded_source)?;
+            store.update_branch_seed_status(&branch.id, SeedStatus::Seeding)?;
+        }
+
+        let result = self
+            .run_seed(branch_name, &branch.container_name, &parsed, &branch.id)
+            .await;
+        self.store().mark_branch_data_changed(&branch.id)?;
+
+        // A broken seed must not look like a healthy branch: mark it failed so
+        // reconciliation keeps it that way and children refuse to clone from it.
+        match result {
+            Ok(()) => {
+                self.store()
+                    .update_branch_seed_status(&branch.id, SeedStatus::Seeded)?;
+                Ok(())
+            }
+            Err(err) => {
+                let store = self.store();
+                store.update_branch_seed_status(&branch.id, SeedStatus::Failed)?;
+                store.update_branch_state(&branch.id, BranchState::Failed)?;
+                Err(err)
+            }
+        }
+    }
+
+    async fn refresh_branch(&self, branch_name: &str) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        self.reconcile_project(&project).await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+        let source = project.seed_source.clone().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Project '{}' has no recorded seed source (initialize with 'pgbranch init --from <source>')",
+                project.name
+            )
+        })?;
+        let parsed = seed::parse_source(&source)?;
+
+        // Seed into a brand-new data dir with a temporary container, leaving
+        // the current branch untouched until the new data is known to be good.
+        let generation_id = Uuid::new_v4().to_string();
+        let new_data_dir = self
+            .data_root
+            .join("projects")
+            .join(&project.id)
+            .join("branches")
+            .join(&generation_id)
+            .join("pgdata");
+        let new_metadata = self
+            .storage
+            .create_empty_branch(&project, &generation_id, &new_data_dir)
+            .await?;
+
+        let staging = model::Branch {
+            id: generation_id.clone(),
+            data_dir: new_data_dir.to_string_lossy().to_string(),
+            container_name: format!("{}-refresh", branch.container_name),
+            storage_metadata: new_metadata,
+            ..branch.clone()
+        };
+
+        let staged = async {
+            let port = self
+                .assign_port(&format!("{}-refresh", branch_name))
+                .await?;
+
+            self.runtime
+                .start_branch(&StartBranchSpec {
+                    image: self.runtime_image(&project, &branch).await?,
+                    container_name: staging.container_name.clone(),
+                    data_dir: new_data_dir.clone(),
+                    port,
+                    pg_user: self.pg_user.clone(),
+                    pg_password: self.password(&project),
+                    pg_db: self.pg_db.clone(),
+                    // The live branch keeps serving on its socket meanwhile
+                    options: self.container_options_with(&branch, false)?,
+                })
+                .await?;
+            self.runtime
+                .wait_ready(
+                    &staging.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    STARTUP_TIMEOUT,
+                )
+                .await?;
+
+            self.run_seed(
+                branch_name,
+                &staging.container_name,
+                &parsed,
+                &generation_id,
+            )
+            .await?;
+
+            // Stop cleanly so the data dir is consistent before the swap
+            self.runtime.stop_branch(&staging.container_name).await
+        }
+        .await;
+
+        if let Err(e) = self.runtime.remove_branch(&staging.container_name).await {
+            log::warn!(
+                "Failed to remove staging container '{}': {}",
+                staging.container_name,
+                e
+            );
+        }
+
+        if let Err(err) = staged {
+            if let Err(e) = self.storage.delete_branch_data(&project, &staging).await {
+                log::warn!("Failed to remove staging data for refresh: {}", e);
+            }
+            return Err(err.context(format!(
+                "refresh of '{}' failed; existing data left untouched",
+                branch_name
+            )));
+        }
+
+        let was_running = branch.state == BranchState::Running;
+        self.swap_refreshed_data(&project, &branch, &staging)
+            .await?;
+
+        if !was_running {
+            self.runtime.stop_branch(&branch.container_name).await?;
+        }
+
+        {
+            let store = self.store();
+            store.update_branch_state(
+                &branch.id,
+                if was_running {
+                    BranchState::Running
+                } else {
+                    BranchState::Stopped
+                },
+            )?;
+            store.update_branch_seed_status(&branch.id, SeedStatus::Seeded)?;
+        }
+
+        // Children cloned from the previous data keep it alive until they go
+        if let Err(e) = self.storage.retire_branch_data(&project, &branch).await {
+            log::warn!(
+                "Failed to remove previous data for '{}' at {}: {}",
+                branch_name,
+                branch.data_dir,
+                e
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn warm_pool_status(&self) -> Result<Option<WarmPoolStatus>> {
+        let Some(pool) = &self.warm_pool else {
+            return Ok(None);
+        };
+        let project = self.ensure_project().await?;
+        let members = self.pool_branches(&project)?;
+        let ready = members
+            .iter()
+            .filter(|b| b.state == BranchState::Running)
+            .count();
+        Ok(Some(WarmPoolStatus {
+            size: pool.size,
+            from: pool.from.clone(),
+            ready,
+            pending: members.len() - ready,
+        }))
+    }
+
+    async fn fill_warm_pool(&self) -> Result<Vec<String>> {
+        let project = self.ensure_project().await?;
+        self.fill_pool(&project).await
+    }
+
+    async fn drain_warm_pool(&self) -> Result<Vec<String>> {
+        let project = self.ensure_project().await?;
+        self.drain_pool(&project).await
     }
 
-    async fn stop_branch(&self, branch_name: &str) -> Result<()> {
+    async fn extension_versions(&self) -> Result<Vec<BranchExtensions>> {
+        if self.extensions.is_empty() && self.dockerfile.is_none() {
+            return Ok(vec![]);
+        }
+
         let project = self.ensure_project().await?;
+        let branches = self.store().list_branches(&project.id)?;
+        let mut result = Vec::new();
+        for branch in branches
+            .into_iter()
+            .filter(|b| b.state == BranchState::Running)
+            .filter(|b| !pool::is_pool_branch(&b.name))
+        {
+            let out = self
+                .runtime
+                .psql(
+                    &branch.container_name,
+                    &self.pg_user,
+                    &self.pg_db,
+                    "SELECT extname, extversion FROM pg_extension \
+                     WHERE extname <> 'plpgsql' ORDER BY extname",
+                )
+                .await
+                .with_context(|| format!("failed to list extensions on '{}'", branch.name))?;
+            let extensions = out
+                .lines()
+                .filter_map(|line| line.split_once('|'))
+                .map(|(name, version)| ExtensionVersion {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                })
+                .collect();
+            result.push(BranchExtensions {
+                branch: branch.name,
+                extensions,
+            });
+        }
+        Ok(result)
+    }
 
+    async fn branch_settings(&self, branch_name: &str) -> Result<Vec<PostgresSetting>> {
+        let project = self.ensure_project().await?;
         let branch = self
             .store()
             .get_branch_by_name(&project.id, branch_name)?
             .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
-
-        self.runtime.stop_branch(&branch.container_name).await?;
-        self.store()
-            .update_branch_state(&branch.id, BranchState::Stopped)?;
-
-        Ok(())
+        self.effective_settings(&branch)
     }
 
-    async fn reset_branch(&self, branch_name: &str) -> Result<()> {
+    async fn set_branch_settings(
+        &self,
+        branch_name: &str,
+        settings: &[(String, String)],
+    ) -> Result<SettingsApplied> {
         let project = self.ensure_project().await?;
-
+        let _lock = self.lock_project().await?;
         let branch = self
             .store()
             .get_branch_by_name(&project.id, branch_name)?
             .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
 
-        let was_running = branch.state == BranchState::Running;
-
-        // Stop container
-        self.runtime.stop_branch(&branch.container_name).await?;
+        for (name, value) in settings {
+            validate_setting(name, value)?;
+        }
+        let previous = self.store().list_branch_settings(&branch.id)?;
+        {
+            let store = self.store();
+            for (name, value) in settings {
+                store.set_branch_setting(&branch.id, name, value)?;
+            }
+        }
 
-        // Re-clone from parent if available
-        if let Some(parent_id) = &branch.parent_branch_id {
-            let parent = self
-                .store()
-                .list_branches(&project.id)?
-                .into_iter()
-                .find(|b| &b.id == parent_id);
+        let names: Vec<String> = settings.iter().map(|(name, _)| name.clone()).collect();
+        self.change_settings(&project, &branch, previous, &names)
+            .await
+    }
 
-            if let Some(parent_branch) = parent {
-                let parent_running = self
-                    .runtime
-                    .container_status(&parent_branch.container_name)
-                    .await?
-                    == docker::ContainerStatus::Running;
+    async fn unset_branch_settings(
+        &self,
+        branch_name: &str,
+        names: &[String],
+    ) -> Result<Option<SettingsApplied>> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
 
-                if parent_running {
-                    self.runtime
-                        .pause_branch(&parent_branch.container_name)
-                        .await?;
+        let previous = self.store().list_branch_settings(&branch.id)?;
+        let mut changed = Vec::new();
+        {
+            let store = self.store();
+            for name in names {
+                if store.unset_branch_setting(&branch.id, name)? {
+                    changed.push(name.clone());
+                } else {
+                    log::warn!("Setting '{}' is not set on branch '{}'", name, branch_name);
                 }
+            }
+        }
 
-                let data_dir = PathBuf::from(&branch.data_dir);
-                let new_metadata = self
-                    .storage
-                    .clone_branch_from_parent(&project, &parent_branch, &branch.id, &data_dir)
-                    .await?;
+        if changed.is_empty() {
+            return Ok(None);
+        }
+        self.change_settings(&project, &branch, previous, &changed)
+            .await
+            .map(Some)
+    }
 
-                if parent_running {
-                    self.runtime
-                        .unpause_branch(&parent_branch.container_name)
-                        .await?;
-                }
+    async fn upgrade(
+        &self,
+        image: &str,
+        branch_name: Option<&str>,
+        options: &UpgradeOptions,
+    ) -> Result<Vec<String>> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        self.reconcile_project(&project).await?;
 
-                if let Some(metadata) = &new_metadata {
-                    self.store()
-                        .update_branch_storage_metadata(&branch.id, Some(metadata))?;
-                }
-            }
+        let new_major = self.runtime.image_pg_major(image).await?;
+        if branch_name.is_none() {
+            // Pool clones would keep the old image; they are cheaper to replace
+            self.drain_pool(&project).await?;
         }
+        let targets = self.upgrade_targets(&project, branch_name)?;
 
-        // Restart if it was running
-        if was_running {
-            self.runtime
-                .start_branch(&StartBranchSpec {
-                    image: project.image.clone(),
-                    container_name: branch.container_name.clone(),
-                    data_dir: PathBuf::from(&branch.data_dir),
-                    port: branch.port,
-                    pg_user: self.pg_user.clone(),
-                    pg_password: self.pg_password.clone(),
-                    pg_db: self.pg_db.clone(),
-                })
-                .await?;
-
-            self.runtime
-                .wait_ready(
-                    &branch.container_name,
-                    &self.pg_user,
-                    &self.pg_db,
-                    STARTUP_TIMEOUT,
-                )
+        let mut upgraded = Vec::new();
+        for branch in &targets {
+            self.drop_sync(branch).await;
+            self.upgrade_branch(&project, branch, image, new_major, options)
                 .await?;
-            self.store()
-                .update_branch_state(&branch.id, BranchState::Running)?;
-        } else {
-            self.store()
-                .update_branch_state(&branch.id, BranchState::Stopped)?;
+            upgraded.push(branch.name.clone());
         }
 
-        Ok(())
-    }
+        if branch_name.is_none() {
+            self.store().update_project_image(&project.id, image)?;
+        }
+        self.normalize_images(&project)?;
 
-    fn supports_lifecycle(&self) -> bool {
-        true
+        Ok(upgraded)
     }
 
-    async fn test_connection(&self) -> Result<()> {
-        let doctor = self.runtime.doctor().await;
-        if !doctor.available {
-            anyhow::bail!("Docker is not available: {}", doctor.detail);
-        }
-        Ok(())
-    }
+    async fn rollback_upgrade(&self, branch_name: Option<&str>) -> Result<Vec<String>> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        let targets = self.upgrade_targets(&project, branch_name)?;
+
+        let mut restored = Vec::new();
+        for branch in &targets {
+            let Some(snapshot) = self.store().get_upgrade_snapshot(&branch.id)? else {
+                if branch_name.is_some() {
+                    anyhow::bail!("Branch '{}' has no upgrade snapshot", branch.name);
+                }
+                continue;
+            };
 
-    async fn doctor(&self) -> Result<DoctorReport> {
-        let mut checks = vec![];
+            let was_running = self
+                .runtime
+                .container_status(&branch.container_name)
+                .await?
+                == docker::ContainerStatus::Running;
+            self.runtime.remove_branch(&branch.container_name).await?;
+
+            // Point the branch back at the snapshot, then drop the upgraded data
+            {
+                let store = self.store();
+                store.update_branch_data(
+                    &branch.id,
+                    &snapshot.data_dir,
+                    snapshot.storage_metadata.as_deref(),
+                )?;
+                store.update_branch_image(&branch.id, Some(&snapshot.image))?;
+                store.delete_upgrade_snapshot(&branch.id)?;
+            }
+            if let Err(e) = self.storage.delete_branch_data(&project, branch).await {
+                log::warn!(
+                    "Failed to remove upgraded data for '{}': {}",
+                    branch.name,
+                    e
+                );
+            }
 
-        // Docker check
-        let docker_result = self.runtime.doctor().await;
-        checks.push(DoctorCheck {
-            name: "Docker".to_string(),
-            available: docker_result.available,
-            detail: if let Some(version) = docker_result.version {
-                format!("Docker {} available", version)
+            let branch = model::Branch {
+                data_dir: snapshot.data_dir,
+                storage_metadata: snapshot.storage_metadata,
+                image: Some(snapshot.image),
+                ..branch.clone()
+            };
+            if was_running {
+                self.wake_branch(&project, &branch).await?;
             } else {
-                docker_result.detail
-            },
-        });
-
-        // Storage check
-        let storage_report = self.storage.doctor().await;
-        for entry in &storage_report.entries {
-            if entry.available || entry.selected {
-                checks.push(DoctorCheck {
-                    name: format!("Storage: {}", entry.kind),
-                    available: entry.available,
-                    detail: entry.detail.clone(),
-                });
+                self.store()
+                    .update_branch_state(&branch.id, BranchState::Stopped)?;
             }
+            restored.push(branch.name);
         }
 
-        checks.push(DoctorCheck {
-            name: "Default storage".to_string(),
-            available: true,
-            detail: format!(
-                "Using {} for new projects",
-                storage_report.default_backend.as_str()
-            ),
-        });
+        self.normalize_images(&project)?;
+        Ok(restored)
+    }
 
-        // State database
-        checks.push(DoctorCheck {
-            name: "State database".to_string(),
-            available: true,
-            detail: format!("{}/state.db", self.data_root.display()),
-        });
+    async fn discard_upgrade_snapshots(&self, branch_name: Option<&str>) -> Result<Vec<String>> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        let mut discarded = Vec::new();
+        for branch in self.upgrade_targets(&project, branch_name)? {
+            if self.discard_upgrade_snapshot(&project, &branch).await? {
+                discarded.push(branch.name);
+            }
+        }
+        Ok(discarded)
+    }
 
-        Ok(DoctorReport { checks })
+    async fn stop_idle_branches(&self) -> Result<Vec<String>> {
+        let project = self.ensure_project().await?;
+        let _lock = self.lock_project().await?;
+        self.reconcile_project(&project).await?;
+        self.stop_idle(&project).await
     }
 
-    async fn init_project(&self, _project_name: &str) -> Result<()> {
-        let _project = self.ensure_project().await?;
-        Ok(())
+    fn stops_idle_branches(&self) -> bool {
+        self.idle_timeout.is_some()
     }
 
-    async fn seed_from_source(&self, branch_name: &str, source: &str) -> Result<()> {
+    async fn watch_idle_branches(&self) -> Result<()> {
+        self.watch_idle().await
+    }
+
+    async fn sync_status(&self) -> Result<Option<SyncStatus>> {
+        let Some(repl) = &self.replication else {
+            return Ok(None);
+        };
         let project = self.ensure_project().await?;
-        let branch = self
-            .store()
-            .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
-        let parsed = seed::parse_source(source)?;
-        seed::seed_branch(
-            self.runtime.client(),
-            &parsed,
-            &branch.container_name,
-            &self.pg_user,
-            &self.pg_db,
-            &self.image,
+        let Some(branch) = self.store().get_branch_by_name(&project.id, &repl.branch)? else {
+            return Ok(None);
+        };
+
+        if self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            != docker::ContainerStatus::Running
+        {
+            return Ok(Some(SyncStatus {
+                branch: branch.name,
+                subscription: repl.subscription.clone(),
+                enabled: false,
+                worker_running: false,
+                lag_seconds: None,
+            }));
+        }
+
+        replication::status(
+            &self.sync_target(&branch.container_name),
+            &branch.name,
+            &repl.subscription,
         )
         .await
+        .map(Some)
     }
 
     fn project_info(&self) -> Option<ProjectInfo> {
@@ -663,6 +3028,42 @@ impl DatabaseBranchingBackend for LocalBackend {
         true
     }
 
+    async fn stream_logs(
+        &self,
+        branch_name: &str,
+        options: &LogOptions,
+        on_line: &mut (dyn for<'l> FnMut(&'l str) + Send),
+    ) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+
+        if self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            == docker::ContainerStatus::NotFound
+        {
+            anyhow::bail!(
+                "Branch '{}' has no container yet; start it first",
+                branch.name
+            );
+        }
+
+        let since = options
+            .since
+            .map(|window| Utc::now().timestamp() - window.as_secs() as i64);
+        self.runtime
+            .stream_logs(&branch.container_name, options.follow, since, on_line)
+            .await
+    }
+
+    fn port_reassignments(&self) -> Vec<PortReassignment> {
+        self.port_reassignments.lock().unwrap().clone()
+    }
+
     async fn destroy_preview(&self) -> Result<Option<(String, Vec<String>)>> {
         let project = match self.store().get_project_by_name(&self.project_name)? {
             Some(p) => p,
@@ -670,22 +3071,32 @@ impl DatabaseBranchingBackend for LocalBackend {
         };
 
         let branches = self.store().list_branches(&project.id)?;
-        let branch_names: Vec<String> = branches.iter().map(|b| b.name.clone()).collect();
+        let branch_names: Vec<String> = branches
+            .iter()
+            .filter(|b| !pool::is_pool_branch(&b.name))
+            .map(|b| b.name.clone())
+            .collect();
 
         Ok(Some((project.name.clone(), branch_names)))
     }
 
     async fn destroy_project(&self) -> Result<Vec<String>> {
+        let _lock = self.lock_project().await?;
         let project = self
             .store()
             .get_project_by_name(&self.project_name)?
             .ok_or_else(|| anyhow::anyhow!("Project '{}' not found", self.project_name))?;
 
         let branches = self.store().list_branches(&project.id)?;
-        let branch_names: Vec<String> = branches.iter().map(|b| b.name.clone()).collect();
+        let branch_names: Vec<String> = branches
+            .iter()
+            .filter(|b| !pool::is_pool_branch(&b.name))
+            .map(|b| b.name.clone())
+            .collect();
 
         // 1. Remove all Docker containers (best-effort)
         for branch in &branches {
+            self.drop_sync(branch).await;
             if let Err(e) = self.runtime.remove_branch(&branch.container_name).await {
                 log::warn!(
                     "Failed to remove container '{}': {}",
@@ -697,6 +3108,12 @@ impl DatabaseBranchingBackend for LocalBackend {
 
         // 2. Delete project-level storage data
         self.storage.delete_project_data(&project).await?;
+        let _ = tokio::fs::remove_dir_all(
+            self.data_root
+                .join("sockets")
+                .join(docker::sanitize(&self.project_name)),
+        )
+        .await;
 
         // 3. Delete project from SQLite (cascades to branches)
         self.store().delete_project(&project.id)?;
@@ -705,6 +3122,135 @@ impl DatabaseBranchingBackend for LocalBackend {
     }
 }
 
+/// Deterministic preferred port for a branch, so it keeps the same port
+/// across machines and re-creation.
+fn hashed_port(project_name: &str, branch_name: &str, start: u16, end: u16) -> u16 {
+    use sha2::{Digest, Sha256};
+
+    let digest = Sha256::new()
+        .chain_update(project_name.as_bytes())
+        .chain_update([0])
+        .chain_update(branch_name.as_bytes())
+        .finalize();
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    let size = u32::from(end - start) + 1;
+    start + (value % size) as u16
+}
+
+/// Setting names as a SQL list; [`validate_setting`] keeps them free of quotes.
+fn sql_names(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("'{}'", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Reject setting names and values that cannot be written to a config file.
+fn validate_setting(name: &str, value: &str) -> Result<()> {
+    let valid_name = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !valid_name {
+        anyhow::bail!("Invalid PostgreSQL setting name '{}'", name);
+    }
+    if value.contains(['\n', '\0']) {
+        anyhow::bail!("Invalid value for PostgreSQL setting '{}'", name);
+    }
+    Ok(())
+}
+
+/// Turn the user-facing container settings into Docker API values. Relative
+/// mount paths resolve against `base_dir`.
+fn resolve_container_options(
+    config: &ContainerConfig,
+    base_dir: &Path,
+) -> Result<ContainerOptions> {
+    use bollard::models::RestartPolicyNameEnum;
+
+    let restart_policy = match config.restart.as_deref() {
+        None => None,
+        Some("no") => Some(RestartPolicyNameEnum::NO),
+        Some("always") => Some(RestartPolicyNameEnum::ALWAYS),
+        Some("unless-stopped") => Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+        Some("on-failure") => Some(RestartPolicyNameEnum::ON_FAILURE),
+        Some(other) => anyhow::bail!(
+            "Invalid restart policy '{}': use no, always, unless-stopped or on-failure",
+            other
+        ),
+    };
+
+    let nano_cpus = match config.cpus {
+        Some(cpus) if cpus <= 0.0 => anyhow::bail!("cpus must be greater than 0"),
+        Some(cpus) => Some((cpus * 1_000_000_000.0) as i64),
+        None => None,
+    };
+
+    let mounts = config
+        .mounts
+        .iter()
+        .map(|mount| {
+            let (host, target) = mount.split_once(':').ok_or_else(|| {
+                anyhow::anyhow!("Invalid mount '{}': expected host:container", mount)
+            })?;
+            let host = resolve_path(base_dir, host);
+            Ok(format!("{}:{}", host.display(), target))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ContainerOptions {
+        memory: config.memory.as_deref().map(parse_size).transpose()?,
+        nano_cpus,
+        shm_size: config.shm_size.as_deref().map(parse_size).transpose()?,
+        restart_policy,
+        env: config
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect(),
+        labels: config.labels.clone().into_iter().collect(),
+        mounts,
+        postgres_settings: Vec::new(),
+        bind_address: None,
+        files: Vec::new(),
+        extra_hosts: Vec::new(),
+    })
+}
+
+/// Running branches last used before `cutoff` (ms since the epoch) that idle
+/// auto-stop may stop.
+fn idle_candidates(
+    branches: Vec<model::Branch>,
+    cutoff: i64,
+    sync_branch: Option<&str>,
+) -> Vec<model::Branch> {
+    branches
+        .into_iter()
+        .filter(|b| b.state == BranchState::Running)
+        .filter(|b| b.last_used_at.unwrap_or(b.created_at) < cutoff)
+        // Pool clones wait unused by design
+        .filter(|b| !pool::is_pool_branch(&b.name))
+        // The sync branch has to stay up to keep applying upstream changes
+        .filter(|b| sync_branch != Some(b.name.as_str()))
+        .collect()
+}
+
+/// Expand `~/` and resolve a relative path against `base_dir`.
+/// Length of the server socket's path in `dir`, when it is longer than
+/// most clients accept.
+fn socket_path_too_long(dir: &Path) -> Option<usize> {
+    let len = dir.join(".s.PGSQL.5432").as_os_str().len();
+    (len > MAX_SOCKET_PATH).then_some(len)
+}
+
+fn resolve_path(base_dir: &Path, path: &str) -> PathBuf {
+    base_dir.join(shellexpand(path))
+}
+
 fn shellexpand(path: &str) -> String {
     if let Some(stripped) = path.strip_prefix("~/") {
         if let Some(home) = dirs::home_dir() {
@@ -713,3 +3259,321 @@ fn shellexpand(path: &str) -> String {
     }
     path.to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashed_ports_are_stable_and_in_range() {
+        let port = hashed_port("app", "feature-x", 55432, 56431);
+        assert_eq!(port, hashed_port("app", "feature-x", 55432, 56431));
+        assert!((55432..=56431).contains(&port));
+        assert_eq!(hashed_port("app", "main", 6000, 6000), 6000);
+    }
+
+    #[test]
+    fn long_socket_paths_are_detected() {
+        assert_eq!(
+            socket_path_too_long(Path::new("/tmp/pgbranch/sockets/app/main")),
+            None
+        );
+        let dir = PathBuf::from("/home/someone")
+            .join("x".repeat(80))
+            .join("sockets/app/main");
+        let len = dir.join(".s.PGSQL.5432").as_os_str().len();
+        assert!(len > MAX_SOCKET_PATH);
+        assert_eq!(socket_path_too_long(&dir), Some(len));
+    }
+
+    #[test]
+    fn idle_candidates_skip_recent_pool_and_sync_branches() {
+        let branch = |name: &str, state: BranchState, last_used_at: Option<i64>| model::Branch {
+            id: name.to_string(),
+            project_id: "p1".to_string(),
+            name: name.to_string(),
+            parent_branch_id: None,
+            state,
+            data_dir: format!("/tmp/{name}/pgdata"),
+            container_name: format!("pgbranch-app-{name}"),
+            port: 55432,
+            storage_metadata: None,
+            created_at: 100,
+            seed_status: None,
+            last_used_at,
+            image: None,
+            port_pinned: false,
+            data_changed_at: None,
+        };
+        let branches = vec![
+            branch("idle", BranchState::Running, Some(500)),
+            branch("never-used", BranchState::Running, None),
+            branch("recent", BranchState::Running, Some(1500)),
+            branch("stopped", BranchState::Stopped, Some(500)),
+            branch("_warm_1", BranchState::Running, Some(500)),
+            branch("main", BranchState::Running, Some(500)),
+        ];
+
+        let names = |branches: Vec<model::Branch>| -> Vec<String> {
+            branches.into_iter().map(|b| b.name).collect()
+        };
+        assert_eq!(
+            names(idle_candidates(branches.clone(), 1000, Some("main"))),
+            ["idle", "never-used"]
+        );
+        assert_eq!(
+            names(idle_candidates(branches.clone(), 1000, None)),
+            ["idle", "never-used", "main"]
+        );
+        assert_eq!(
+            names(idle_candidates(branches, i64::MAX, Some("main"))),
+            ["idle", "never-used", "recent"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn leftover_creations_are_undone_on_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        // Undoing storage and state steps needs no Docker daemon
+        let socket = dir.path().join("docker.sock");
+        std::fs::write(&socket, "").unwrap();
+        let client = bollard::Docker::connect_with_unix(
+            &socket.to_string_lossy(),
+            5,
+            bollard::API_DEFAULT_VERSION,
+        )
+        .unwrap();
+        let local_config = LocalBackendConfig {
+            data_root: Some(dir.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let backend = LocalBackend::with_runtime(
+            "app",
+            &Config::default(),
+            Some(&local_config),
+            DockerRuntime::with_client(client),
+        )
+        .await
+        .unwrap();
+        let project = backend.ensure_project().await.unwrap();
+
+        // What a process killed halfway through creating 'feature' leaves behind
+        let data_dir = dir.path().join("projects/leftover/pgdata");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("PG_VERSION"), "17\n").unwrap();
+        backend
+            .store()
+            .create_branch(NewBranch {
+                id: "b1".to_string(),
+                project_id: project.id.clone(),
+                name: "feature".to_string(),
+                parent_branch_id: None,
+                state: BranchState::Provisioning,
+                data_dir: data_dir.to_string_lossy().to_string(),
+                container_name: "pgbranch-app-feature".to_string(),
+                port: 55432,
+                storage_metadata: None,
+                image: None,
+                port_pinned: false,
+            })
+            .unwrap();
+        backend
+            .store()
+            .save_create_journal(&model::CreateJournal {
+                branch_id: "b1".to_string(),
+                project_id: project.id.clone(),
+                branch_name: "feature".to_string(),
+                steps: vec![
+                    CreateStep::Storage {
+                        data_dir: data_dir.to_string_lossy().to_string(),
+                        storage_metadata: None,
+                    },
+                    CreateStep::Row,
+                ],
+                started_at: backend.started_at - 1,
+            })
+            .unwrap();
+
+        backend
+            .recovered
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        backend.ensure_project().await.unwrap();
+
+        assert!(!data_dir.exists());
+        let store = backend.store();
+        assert!(store
+            .get_branch_by_name(&project.id, "feature")
+            .unwrap()
+            .is_none());
+        assert!(store
+            .list_create_journals(&project.id, None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn failed_refresh_swap_rolls_back_to_the_previous_data() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        // A Docker API without any images or containers, so the refreshed
+        // container can never start
+        let socket = dir.path().join("docker.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let body = r#"{"message":"not found"}"#;
+                    let response = format!(
+                        "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+        let client = bollard::Docker::connect_with_unix(
+            &socket.to_string_lossy(),
+            5,
+            bollard::API_DEFAULT_VERSION,
+        )
+        .unwrap();
+        let local_config = LocalBackendConfig {
+            data_root: Some(dir.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let backend = LocalBackend::with_runtime(
+            "app",
+            &Config::default(),
+            Some(&local_config),
+            DockerRuntime::with_client(client),
+        )
+        .await
+        .unwrap();
+        let project = backend.ensure_project().await.unwrap();
+
+        let data_dir = |id: &str| {
+            let data_dir = dir
+                .path()
+                .join("projects/p/branches")
+                .join(id)
+                .join("pgdata");
+            std::fs::create_dir_all(&data_dir).unwrap();
+            std::fs::write(data_dir.join("PG_VERSION"), "17\n").unwrap();
+            data_dir.to_string_lossy().to_string()
+        };
+        let branch = backend
+            .store()
+            .create_branch(NewBranch {
+                id: "b1".to_string(),
+                project_id: project.id.clone(),
+                name: "main".to_string(),
+                parent_branch_id: None,
+                state: BranchState::Stopped,
+                data_dir: data_dir("b1"),
+                container_name: "pgbranch-app-main".to_string(),
+                port: 55432,
+                storage_metadata: None,
+                image: None,
+                port_pinned: false,
+            })
+            .unwrap();
+        let staging = model::Branch {
+            id: "g1".to_string(),
+            data_dir: data_dir("g1"),
+            container_name: "pgbranch-app-main-refresh".to_string(),
+            ..branch.clone()
+        };
+
+        let err = backend
+            .swap_refreshed_data(&project, &branch, &staging)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("rolled back"), "{err:#}");
+        let restored = backend
+            .store()
+            .get_branch_by_name(&project.id, "main")
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.data_dir, branch.data_dir);
+        assert!(Path::new(&branch.data_dir).join("PG_VERSION").exists());
+        assert!(!Path::new(&staging.data_dir).exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn branch_sockets_are_mounted_from_a_private_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        // Building container options needs no Docker daemon
+        let socket = dir.path().join("docker.sock");
+        std::fs::write(&socket, "").unwrap();
+        let client = bollard::Docker::connect_with_unix(
+            &socket.to_string_lossy(),
+            5,
+            bollard::API_DEFAULT_VERSION,
+        )
+        .unwrap();
+        let local_config = LocalBackendConfig {
+            data_root: Some(dir.path().to_string_lossy().to_string()),
+            unix_sockets: Some(true),
+            ..Default::default()
+        };
+        let backend = LocalBackend::with_runtime(
+            "app",
+            &Config::default(),
+            Some(&local_config),
+            DockerRuntime::with_client(client),
+        )
+        .await
+        .unwrap();
+        let branch = model::Branch {
+            id: "b1".to_string(),
+            project_id: "p1".to_string(),
+            name: "feature/x".to_string(),
+            parent_branch_id: Some("b0".to_string()),
+            state: BranchState::Running,
+            data_dir: dir
+                .path()
+                .join("projects/p1/branches/b1/pgdata")
+                .to_string_lossy()
+                .to_string(),
+            container_name: "pgbranch-app-feature-x".to_string(),
+            port: 55432,
+            storage_metadata: None,
+            created_at: 100,
+            seed_status: None,
+            last_used_at: None,
+            image: None,
+            port_pinned: false,
+            data_changed_at: None,
+        };
+
+        let socket_dir = dir
+            .path()
+            .join("sockets/app")
+            .join(docker::sanitize("feature/x"));
+        let mount = format!("{}:{}", socket_dir.display(), SOCKET_CONTAINER_DIR);
+        let options = backend.container_options_for(&branch).unwrap();
+        assert!(options.mounts.contains(&mount), "{:?}", options.mounts);
+        let mode = std::fs::metadata(&socket_dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // A second container for the branch leaves its socket alone
+        let options = backend.container_options_with(&branch, false).unwrap();
+        assert!(!options.mounts.contains(&mount));
+    }
+}
@@ -8,6 +8,9 @@ pub struct Project {
     pub storage_backend: StorageBackend,
     pub storage_config: Option<String>,
     pub created_at: i64,
+    pub seed_source: Option<String>,
+    /// Generated superuser password; projects created before it existed use the default
+    pub password: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +25,55 @@ pub struct Branch {
     pub port: u16,
     pub storage_metadata: Option<String>,
     pub created_at: i64,
+    pub seed_status: Option<SeedStatus>,
+    pub last_used_at: Option<i64>,
+    /// Image override; `None` runs the project image
+    pub image: Option<String>,
+    /// Set with `create --port`; such ports are never reassigned
+    pub port_pinned: bool,
+    /// When reset, refresh, seed or upgrade last replaced the branch's data
+    pub data_changed_at: Option<i64>,
+}
+
+/// Something `create` did that has to be undone if it does not finish.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum CreateStep {
+    /// Replication sync into the parent was paused for the clone
+    PausedSync { parent_branch: String },
+    /// The parent container was paused for the clone
+    PausedParent { container_name: String },
+    /// Storage allocated for the branch; metadata is known once it exists
+    Storage {
+        data_dir: String,
+        storage_metadata: Option<String>,
+    },
+    /// The branch row was inserted
+    Row,
+    /// A container was started for the branch
+    Container { container_name: String },
+}
+
+/// Steps taken by a `create` that has not finished, oldest first.
+#[derive(Debug, Clone)]
+pub struct CreateJournal {
+    pub branch_id: String,
+    pub project_id: String,
+    pub branch_name: String,
+    pub steps: Vec<CreateStep>,
+    pub started_at: i64,
+}
+
+/// Copy of a branch's data taken before a major-version upgrade.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpgradeSnapshot {
+    pub branch_id: String,
+    pub snapshot_id: String,
+    pub data_dir: String,
+    pub storage_metadata: Option<String>,
+    /// Image the branch ran before the upgrade
+    pub image: String,
+    pub created_at: i64,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -60,6 +112,8 @@ pub enum BranchState {
     Provisioning,
     Stopped,
     Running,
+    /// Stopped automatically after `idle_timeout`; started again on demand.
+    IdleStopped,
     Failed,
 }
 
@@ -69,6 +123,7 @@ impl BranchState {
             Self::Provisioning => "provisioning",
             Self::Stopped => "stopped",
             Self::Running => "running",
+            Self::IdleStopped => "idle_stopped",
             Self::Failed => "failed",
         }
     }
@@ -78,6 +133,34 @@ impl BranchState {
             "provisioning" => Some(Self::Provisioning),
             "stopped" => Some(Self::Stopped),
             "running" => Some(Self::Running),
+            "idle_stopped" => Some(Self::IdleStopped),
+            "failed" => Some(Self::Failed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedStatus {
+    Seeding,
+    Seeded,
+    Failed,
+}
+
+impl SeedStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Seeding => "seeding",
+            Self::Seeded => "seeded",
+            Self::Failed => "failed",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "seeding" => Some(Self::Seeding),
+            "seeded" => Some(Self::Seeded),
             "failed" => Some(Self::Failed),
             _ => None,
         }
@@ -0,0 +1,286 @@
+use anyhow::Result;
+use uuid::Uuid;
+
+use std::path::PathBuf;
+
+use super::docker::{ContainerStatus, ReserveBranchSpec, StartBranchSpec};
+use super::model::{self, BranchState};
+use super::{LocalBackend, STARTUP_TIMEOUT};
+use crate::backends::{BranchInfo, CreateBranchOptions};
+
+/// Names of warm pool clones start with this; users cannot create such branches.
+pub const POOL_PREFIX: &str = "_warm_";
+
+pub fn is_pool_branch(branch_name: &str) -> bool {
+    branch_name.starts_with(POOL_PREFIX)
+}
+
+impl LocalBackend {
+    /// Clones in the warm pool, newest first.
+    pub(super) fn pool_branches(&self, project: &model::Project) -> Result<Vec<model::Branch>> {
+        Ok(self
+            .store()
+            .list_branches(&project.id)?
+            .into_iter()
+            .filter(|b| is_pool_branch(&b.name))
+            .collect())
+    }
+
+    /// Whether a pool clone still matches its parent: a pool clone taken
+    /// before the pool's branch was reset, refreshed, seeded or upgraded is not.
+    fn is_current_clone(&self, member: &model::Branch, parent: &model::Branch) -> bool {
+        member.parent_branch_id.as_deref() == Some(parent.id.as_str())
+            && member.image == parent.image
+            && parent
+                .data_changed_at
+                .is_none_or(|changed_at| member.created_at > changed_at)
+    }
+
+    /// Rename a ready pool clone to `branch_name` when it is what creating the
+    /// branch would produce: a current clone of the pool's branch on the same
+    /// image. The container is recreated when the branch's own port or
+    /// container settings differ from the clone's. This runs without the
+    /// project lock, so a refill in progress does not hold creation up; the
+    /// store picks between racing claims.
+    pub(super) async fn claim_warm_branch(
+        &self,
+        project: &model::Project,
+        branch_name: &str,
+        from_branch: Option<&str>,
+        create_options: &CreateBranchOptions,
+    ) -> Result<Option<BranchInfo>> {
+        let Some(pool) = &self.warm_pool else {
+            return Ok(None);
+        };
+        // Socket directories are named after the branch, so a clone's
+        // would be left behind
+        if pool.size == 0
+            || create_options.image.is_some()
+            || create_options.port.is_some()
+            || self.unix_sockets
+        {
+            return Ok(None);
+        }
+        if self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .is_some()
+        {
+            return Ok(None);
+        }
+
+        let parent = match from_branch {
+            Some(name) => self.store().get_branch_by_name(&project.id, name)?,
+            None => self.default_parent(project)?,
+        };
+        let Some(parent) = parent.filter(|p| p.name == pool.from) else {
+            return Ok(None);
+        };
+
+        for member in self.pool_branches(project)? {
+            if member.state != BranchState::Running || !self.is_current_clone(&member, &parent) {
+                continue;
+            }
+            if self
+                .runtime
+                .container_status(&member.container_name)
+                .await?
+                != ContainerStatus::Running
+            {
+                continue;
+            }
+
+            let reserved = self
+                .runtime
+                .reserve_branch(&ReserveBranchSpec {
+                    project_name: self.project_name.clone(),
+                    branch_name: branch_name.to_string(),
+                })
+                .await?;
+            let won = self.store().claim_branch(
+                &member.id,
+                &member.name,
+                branch_name,
+                &reserved.container_name,
+            );
+            match won {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    // Most likely another process just created `branch_name`
+                    log::debug!("Not claiming a warm pool clone: {:#}", err);
+                    return Ok(None);
+                }
+            }
+
+            let claimed = model::Branch {
+                name: branch_name.to_string(),
+                container_name: reserved.container_name.clone(),
+                ..member.clone()
+            };
+            if let Err(err) = self.take_over_container(project, &member, &claimed).await {
+                log::warn!(
+                    "Failed to claim warm pool clone '{}': {:#}",
+                    member.name,
+                    err
+                );
+                // Drop the half-claimed clone; creation then starts afresh
+                let _ = self.runtime.remove_branch(&member.container_name).await;
+                if let Err(err) = self.remove_branch(project, &claimed).await {
+                    log::warn!(
+                        "Failed to remove claimed clone '{}': {:#}",
+                        branch_name,
+                        err
+                    );
+                }
+                return Ok(None);
+            }
+
+            log::info!(
+                "Claimed warm pool clone '{}' as '{}'",
+                member.name,
+                branch_name
+            );
+            let branch = self
+                .store()
+                .get_branch_by_name(&project.id, branch_name)?
+                .unwrap_or(claimed);
+            return Ok(Some(self.branch_info(&branch, Some(parent.name))));
+        }
+        Ok(None)
+    }
+
+    /// Give a claimed clone's container the branch's own name, hashed port
+    /// and settings. Labels and port bindings cannot change on an existing
+    /// container, so it is recreated on the same data unless they match.
+    async fn take_over_container(
+        &self,
+        project: &model::Project,
+        member: &model::Branch,
+        claimed: &model::Branch,
+    ) -> Result<()> {
+        let port = self.assign_port(&claimed.name).await?;
+        let options = self.container_options_for(claimed)?;
+        if port == member.port && options == self.container_options_for(member)? {
+            return self
+                .runtime
+                .rename_branch(&member.container_name, &claimed.container_name)
+                .await;
+        }
+
+        self.runtime.stop_branch(&member.container_name).await?;
+        self.runtime.remove_branch(&member.container_name).await?;
+        self.store().update_branch_port(&claimed.id, port, false)?;
+        self.runtime
+            .start_branch(&StartBranchSpec {
+                image: self.runtime_image(project, claimed).await?,
+                container_name: claimed.container_name.clone(),
+                data_dir: PathBuf::from(&claimed.data_dir),
+                port,
+                pg_user: self.pg_user.clone(),
+                pg_password: self.password(project),
+                pg_db: self.pg_db.clone(),
+                options,
+            })
+            .await?;
+        self.runtime
+            .wait_ready(
+                &claimed.container_name,
+                &self.pg_user,
+                &self.pg_db,
+                STARTUP_TIMEOUT,
+            )
+            .await
+    }
+
+    /// Replace stale or broken pool clones and create new ones until the pool
+    /// is full. Returns the names of the clones created.
+    pub(super) async fn fill_pool(&self, project: &model::Project) -> Result<Vec<String>> {
+        let Some(pool) = &self.warm_pool else {
+            anyhow::bail!("No warm pool configured; set local.warm_pool");
+        };
+        let _lock = self.lock_project().await?;
+        self.reconcile_project(project).await?;
+
+        let parent = self
+            .store()
+            .get_branch_by_name(&project.id, &pool.from)?
+            .ok_or_else(|| anyhow::anyhow!("Warm pool branch '{}' not found", pool.from))?;
+
+        let mut ready = 0;
+        for member in self.pool_branches(project)? {
+            let usable = ready < pool.size
+                && self.is_current_clone(&member, &parent)
+                && match member.state {
+                    BranchState::Running => true,
+                    BranchState::Stopped | BranchState::IdleStopped => {
+                        match self.wake_branch(project, &member).await {
+                            Ok(_) => true,
+                            Err(err) => {
+                                log::warn!(
+                                    "Failed to start warm pool clone '{}': {:#}",
+                                    member.name,
+                                    err
+                                );
+                                false
+                            }
+                        }
+                    }
+                    BranchState::Provisioning | BranchState::Failed => false,
+                };
+            if usable {
+                ready += 1;
+            } else {
+                self.remove_pool_branch(project, &member).await?;
+            }
+        }
+
+        let mut created = Vec::new();
+        while ready + created.len() < pool.size {
+            let simple = Uuid::new_v4().simple().to_string();
+            let name = format!("{}{}", POOL_PREFIX, &simple[..8]);
+            self.create_branch_locked(
+                project,
+                &name,
+                Some(&pool.from),
+                &CreateBranchOptions::default(),
+            )
+            .await?;
+            created.push(name);
+        }
+        Ok(created)
+    }
+
+    /// Remove every pool clone. Returns the names of the clones removed.
+    pub(super) async fn drain_pool(&self, project: &model::Project) -> Result<Vec<String>> {
+        let _lock = self.lock_project().await?;
+        let mut removed = Vec::new();
+        for member in self.pool_branches(project)? {
+            if self.remove_pool_branch(project, &member).await? {
+                removed.push(member.name);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove a pool clone unless a `create` claims it first. Only running
+    /// clones can be claimed, so taking it out of that state settles the race.
+    async fn remove_pool_branch(
+        &self,
+        project: &model::Project,
+        member: &model::Branch,
+    ) -> Result<bool> {
+        if member.state == BranchState::Running
+            && !self.store().update_branch_state_if(
+                &member.id,
+                &member.name,
+                BranchState::Running,
+                BranchState::Provisioning,
+            )?
+        {
+            return Ok(false);
+        }
+        self.remove_branch(project, member).await?;
+        Ok(true)
+    }
+}
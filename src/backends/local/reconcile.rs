@@ -1,5 +1,5 @@
 use super::docker::{ContainerStatus, DockerRuntime};
-use super::model::{Branch, BranchState};
+use super::model::{Branch, BranchState, SeedStatus};
 
 /// Determine state changes needed by checking Docker container states.
 /// Returns a list of (branch_id, new_state) pairs.
@@ -29,6 +29,14 @@ pub async fn compute_state_changes(
 
     let mut changes = vec![];
     for branch in branches {
+        // A failed seed stays failed regardless of what the container is doing
+        if branch.seed_status == Some(SeedStatus::Failed) {
+            if branch.state != BranchState::Failed {
+                changes.push((branch.id, BranchState::Failed));
+            }
+            continue;
+        }
+
         let next_state = match runtime.container_status(&branch.container_name).await {
             Ok(ContainerStatus::Running) => BranchState::Running,
             Ok(ContainerStatus::Paused) => {
@@ -47,10 +55,12 @@ pub async fn compute_state_changes(
             Ok(ContainerStatus::Exited)
             | Ok(ContainerStatus::NotFound)
             | Ok(ContainerStatus::Other(_)) => {
-                if std::path::Path::new(&branch.data_dir).exists() {
-                    BranchState::Stopped
-                } else {
+                if !std::path::Path::new(&branch.data_dir).exists() {
                     BranchState::Failed
+                } else if branch.state == BranchState::IdleStopped {
+                    BranchState::IdleStopped
+                } else {
+                    BranchState::Stopped
                 }
             }
             Err(err) => {
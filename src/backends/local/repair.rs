@@ -0,0 +1,458 @@
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::docker::{self, ContainerStatus};
+use super::model::{self, BranchState, SeedStatus};
+use super::{pool, upgrade, LocalBackend};
+use crate::backends::{PortReassignment, RepairAction, RepairOption, RepairPlan};
+
+impl LocalBackend {
+    /// Plans for one branch, or for every failed or provisioning branch.
+    pub(super) async fn repair_plans_for(
+        &self,
+        project: &model::Project,
+        branch_name: Option<&str>,
+    ) -> Result<Vec<RepairPlan>> {
+        let branches = self.store().list_branches(&project.id)?;
+        let targets: Vec<&model::Branch> = match branch_name {
+            Some(name) => vec![branches
+                .iter()
+                .find(|b| b.name == name)
+                .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", name))?],
+            None => branches
+                .iter()
+                .filter(|b| matches!(b.state, BranchState::Failed | BranchState::Provisioning))
+                // The pool replaces broken clones itself
+                .filter(|b| !pool::is_pool_branch(&b.name))
+                .collect(),
+        };
+
+        let mut plans = Vec::with_capacity(targets.len());
+        for branch in targets {
+            let parent = branch
+                .parent_branch_id
+                .as_ref()
+                .and_then(|id| branches.iter().find(|b| &b.id == id));
+            plans.push(self.diagnose(branch, parent).await);
+        }
+        Ok(plans)
+    }
+
+    async fn diagnose(&self, branch: &model::Branch, parent: Option<&model::Branch>) -> RepairPlan {
+        let data_dir = Path::new(&branch.data_dir);
+        let container = self
+            .runtime
+            .container_status(&branch.container_name)
+            .await
+            .map_err(|err| format!("{:#}", err));
+        let port_taken = !matches!(container, Ok(ContainerStatus::Running))
+            && !docker::port_available(self.runtime.client(), branch.port).await;
+        let probe = Probe {
+            data_dir_exists: data_dir.exists(),
+            has_cluster: upgrade::data_dir_major(data_dir).is_ok(),
+            container,
+            port_taken,
+            parent_has_cluster: parent
+                .is_some_and(|p| upgrade::data_dir_major(Path::new(&p.data_dir)).is_ok()),
+        };
+        plan_repair(
+            branch,
+            parent,
+            &probe,
+            self.port_range_start..=self.port_range_end,
+        )
+    }
+
+    pub(super) async fn apply_repair(
+        &self,
+        project: &model::Project,
+        branch_name: &str,
+        action: RepairAction,
+    ) -> Result<()> {
+        let plan = self
+            .repair_plans_for(project, Some(branch_name))
+            .await?
+            .remove(0);
+        let option = plan
+            .options
+            .iter()
+            .find(|option| option.action == action)
+            .expect("every plan lists every action");
+        if let Some(reason) = &option.unavailable {
+            anyhow::bail!(
+                "Cannot {} branch '{}': {}",
+                action.as_str(),
+                branch_name,
+                reason
+            );
+        }
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+
+        match action {
+            RepairAction::RecreateContainer => {
+                self.runtime.remove_branch(&branch.container_name).await?;
+                self.wake_branch(project, &branch).await?;
+            }
+            RepairAction::Reclone => {
+                let parent = self
+                    .store()
+                    .list_branches(&project.id)?
+                    .into_iter()
+                    .find(|b| Some(&b.id) == branch.parent_branch_id.as_ref())
+                    .ok_or_else(|| anyhow::anyhow!("Parent of '{}' not found", branch.name))?;
+
+                self.runtime.remove_branch(&branch.container_name).await?;
+                self.storage.delete_branch_data(project, &branch).await?;
+                let data_dir = PathBuf::from(&branch.data_dir);
+                let metadata = self
+                    .clone_parent_data(project, &parent, &branch.id, &data_dir, None)
+                    .await?;
+                {
+                    let store = self.store();
+                    store.update_branch_data(&branch.id, &branch.data_dir, metadata.as_deref())?;
+                    store.clear_branch_seed_status(&branch.id)?;
+                }
+
+                self.wake_branch(project, &branch).await?;
+                self.detach_inherited_sync(&parent, &branch.container_name)
+                    .await?;
+            }
+            RepairAction::RepickPort => {
+                self.runtime.remove_branch(&branch.container_name).await?;
+                let port = self.assign_port(&branch.name).await?;
+                // Repicking is how a pinned branch gives up its port
+                self.store().update_branch_port(&branch.id, port, true)?;
+                self.port_reassignments
+                    .lock()
+                    .unwrap()
+                    .push(PortReassignment {
+                        branch: branch.name.clone(),
+                        old_port: branch.port,
+                        new_port: port,
+                    });
+
+                let moved = model::Branch {
+                    port,
+                    port_pinned: false,
+                    ..branch
+                };
+                self.wake_branch(project, &moved).await?;
+            }
+            RepairAction::Forget => {
+                self.drop_sync(&branch).await;
+                // Best effort: parts of a broken branch may already be gone
+                if let Err(err) = self.runtime.remove_branch(&branch.container_name).await {
+                    log::warn!("Failed to remove container of '{}': {:#}", branch.name, err);
+                }
+                if let Err(err) = self.discard_upgrade_snapshot(project, &branch).await {
+                    log::warn!(
+                        "Failed to discard upgrade snapshot of '{}': {:#}",
+                        branch.name,
+                        err
+                    );
+                }
+                if let Err(err) = self.storage.delete_branch_data(project, &branch).await {
+                    log::warn!("Failed to delete data of '{}': {:#}", branch.name, err);
+                }
+                if self.unix_sockets {
+                    let _ = tokio::fs::remove_dir_all(self.socket_dir(&branch.name)).await;
+                }
+                let _ = tokio::fs::remove_dir_all(self.settings_dir(&branch)).await;
+                self.store().delete_branch(&branch.id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What `diagnose` found out about a branch outside the state database.
+#[derive(Debug)]
+struct Probe {
+    data_dir_exists: bool,
+    has_cluster: bool,
+    container: std::result::Result<ContainerStatus, String>,
+    /// The branch's port is held by something other than its own container
+    port_taken: bool,
+    parent_has_cluster: bool,
+}
+
+/// The problems `probe` shows and the repairs available for them. A healthy
+/// branch has no problems.
+fn plan_repair(
+    branch: &model::Branch,
+    parent: Option<&model::Branch>,
+    probe: &Probe,
+    port_range: RangeInclusive<u16>,
+) -> RepairPlan {
+    let mut problems = Vec::new();
+
+    let has_cluster = probe.has_cluster;
+    if !probe.data_dir_exists {
+        problems.push(format!("Data directory {} is missing", branch.data_dir));
+    } else if !has_cluster {
+        problems.push(format!(
+            "Data directory {} holds no PostgreSQL cluster",
+            branch.data_dir
+        ));
+    }
+    if branch.seed_status == Some(SeedStatus::Failed) {
+        problems.push("Seeding or seed verification failed".to_string());
+    }
+
+    match &probe.container {
+        Ok(ContainerStatus::Running) => {}
+        Ok(ContainerStatus::NotFound) => problems.push(format!(
+            "Container {} does not exist",
+            branch.container_name
+        )),
+        Ok(ContainerStatus::Paused) => {
+            problems.push(format!("Container {} is paused", branch.container_name))
+        }
+        Ok(ContainerStatus::Exited) => {
+            problems.push(format!("Container {} is stopped", branch.container_name))
+        }
+        Ok(ContainerStatus::Other(state)) => {
+            problems.push(format!("Container {} is {}", branch.container_name, state))
+        }
+        Err(err) => problems.push(format!("Cannot inspect container: {}", err)),
+    }
+
+    let port_taken = probe.port_taken;
+    if port_taken {
+        problems.push(format!(
+            "Port {} is in use by another process{}",
+            branch.port,
+            if branch.port_pinned { " (pinned)" } else { "" }
+        ));
+    }
+
+    let parent_unusable = match (&branch.parent_branch_id, parent) {
+        (None, _) => Some("the branch has no parent".to_string()),
+        (Some(_), None) => Some("its parent branch no longer exists".to_string()),
+        (Some(_), Some(parent)) if parent.seed_status == Some(SeedStatus::Failed) => {
+            Some(format!("parent '{}' failed seeding", parent.name))
+        }
+        (Some(_), Some(parent)) if !probe.parent_has_cluster => {
+            Some(format!("parent '{}' has no usable data", parent.name))
+        }
+        _ => None,
+    };
+    if branch.parent_branch_id.is_some() && parent.is_none() {
+        problems.push("Parent branch no longer exists".to_string());
+    }
+
+    // Unpinned branches move to a free port on start anyway
+    let pinned_port_taken = (branch.port_pinned && port_taken).then(|| {
+        format!(
+            "pinned port {} is taken; pick a new port instead",
+            branch.port
+        )
+    });
+    let start_step = "Start PostgreSQL and wait until it accepts connections".to_string();
+    let remove_container = format!("Remove container {}", branch.container_name);
+
+    let recreate = RepairOption {
+        action: RepairAction::RecreateContainer,
+        description: "Recreate the container from the existing data".to_string(),
+        steps: vec![
+            remove_container.clone(),
+            format!("Create a new container on {}", branch.data_dir),
+            start_step.clone(),
+        ],
+        unavailable: if !has_cluster {
+            Some("the data directory holds no usable cluster".to_string())
+        } else if branch.seed_status == Some(SeedStatus::Failed) {
+            Some("the seed failed, so the data is incomplete; re-clone, refresh or forget the branch".to_string())
+        } else {
+            pinned_port_taken.clone()
+        },
+    };
+
+    let reclone = RepairOption {
+        action: RepairAction::Reclone,
+        description: "Replace the branch's data with a fresh clone of its parent".to_string(),
+        steps: vec![
+            remove_container.clone(),
+            format!(
+                "Delete {} (changes made on the branch are lost)",
+                branch.data_dir
+            ),
+            format!(
+                "Clone the data of '{}'",
+                parent.map(|p| p.name.as_str()).unwrap_or("parent")
+            ),
+            start_step,
+        ],
+        unavailable: parent_unusable.or(pinned_port_taken),
+    };
+
+    let repick = RepairOption {
+        action: RepairAction::RepickPort,
+        description: "Move the branch to a free port".to_string(),
+        steps: vec![
+            remove_container.clone(),
+            format!(
+                "Pick a free port in {}-{} other than {}{}",
+                port_range.start(),
+                port_range.end(),
+                branch.port,
+                if branch.port_pinned {
+                    " and unpin it"
+                } else {
+                    ""
+                }
+            ),
+            "Start PostgreSQL on the new port".to_string(),
+        ],
+        unavailable: (!has_cluster)
+            .then(|| "the data directory holds no usable cluster".to_string()),
+    };
+
+    let forget = RepairOption {
+        action: RepairAction::Forget,
+        description: "Remove whatever is left of the branch".to_string(),
+        steps: vec![
+            remove_container,
+            format!("Delete {}", branch.data_dir),
+            format!("Delete branch '{}' from the state database", branch.name),
+        ],
+        unavailable: None,
+    };
+
+    RepairPlan {
+        branch: branch.name.clone(),
+        state: branch.state.as_str().to_string(),
+        problems,
+        options: vec![recreate, reclone, repick, forget],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branch(name: &str, parent_branch_id: Option<&str>) -> model::Branch {
+        model::Branch {
+            id: name.to_string(),
+            project_id: "p1".to_string(),
+            name: name.to_string(),
+            parent_branch_id: parent_branch_id.map(str::to_string),
+            state: BranchState::Failed,
+            data_dir: format!("/tmp/{name}/pgdata"),
+            container_name: format!("pgbranch-app-{name}"),
+            port: 55433,
+            storage_metadata: None,
+            created_at: 1,
+            seed_status: None,
+            last_used_at: None,
+            image: None,
+            port_pinned: false,
+            data_changed_at: None,
+        }
+    }
+
+    fn healthy() -> Probe {
+        Probe {
+            data_dir_exists: true,
+            has_cluster: true,
+            container: Ok(ContainerStatus::Running),
+            port_taken: false,
+            parent_has_cluster: true,
+        }
+    }
+
+    fn unavailable(plan: &RepairPlan, action: RepairAction) -> Option<&str> {
+        plan.options
+            .iter()
+            .find(|option| option.action == action)
+            .unwrap()
+            .unavailable
+            .as_deref()
+    }
+
+    #[test]
+    fn healthy_branches_have_no_problems() {
+        let main = branch("main", None);
+        let feature = branch("feature", Some("main"));
+        let plan = plan_repair(&feature, Some(&main), &healthy(), 55432..=56431);
+        assert!(plan.problems.is_empty());
+        assert!(plan.options.iter().all(|o| o.unavailable.is_none()));
+    }
+
+    #[test]
+    fn stuck_branches_can_be_recreated_or_recloned() {
+        let main = branch("main", None);
+        let feature = model::Branch {
+            state: BranchState::Provisioning,
+            ..branch("feature", Some("main"))
+        };
+        let probe = Probe {
+            has_cluster: false,
+            container: Ok(ContainerStatus::NotFound),
+            ..healthy()
+        };
+
+        let plan = plan_repair(&feature, Some(&main), &probe, 55432..=56431);
+        assert_eq!(plan.state, "provisioning");
+        assert_eq!(
+            plan.problems,
+            [
+                "Data directory /tmp/feature/pgdata holds no PostgreSQL cluster",
+                "Container pgbranch-app-feature does not exist",
+            ]
+        );
+        assert!(unavailable(&plan, RepairAction::RecreateContainer).is_some());
+        assert!(unavailable(&plan, RepairAction::RepickPort).is_some());
+        assert_eq!(unavailable(&plan, RepairAction::Reclone), None);
+        assert_eq!(unavailable(&plan, RepairAction::Forget), None);
+    }
+
+    #[test]
+    fn a_taken_pinned_port_must_be_repicked() {
+        let main = branch("main", None);
+        let feature = model::Branch {
+            port_pinned: true,
+            ..branch("feature", Some("main"))
+        };
+        let probe = Probe {
+            container: Ok(ContainerStatus::Exited),
+            port_taken: true,
+            ..healthy()
+        };
+
+        let plan = plan_repair(&feature, Some(&main), &probe, 55432..=56431);
+        assert!(plan
+            .problems
+            .contains(&"Port 55433 is in use by another process (pinned)".to_string()));
+        let reason = "pinned port 55433 is taken; pick a new port instead";
+        assert_eq!(
+            unavailable(&plan, RepairAction::RecreateContainer),
+            Some(reason)
+        );
+        assert_eq!(unavailable(&plan, RepairAction::Reclone), Some(reason));
+        assert_eq!(unavailable(&plan, RepairAction::RepickPort), None);
+
+        // Unpinned branches move off a taken port when started
+        let unpinned = branch("feature", Some("main"));
+        let plan = plan_repair(&unpinned, Some(&main), &probe, 55432..=56431);
+        assert_eq!(unavailable(&plan, RepairAction::RecreateContainer), None);
+    }
+
+    #[test]
+    fn a_missing_parent_rules_out_recloning() {
+        let feature = branch("feature", Some("main"));
+        let plan = plan_repair(&feature, None, &healthy(), 55432..=56431);
+        assert_eq!(plan.problems, ["Parent branch no longer exists"]);
+        assert_eq!(
+            unavailable(&plan, RepairAction::Reclone),
+            Some("its parent branch no longer exists")
+        );
+        assert_eq!(unavailable(&plan, RepairAction::RecreateContainer), None);
+    }
+}
@@ -0,0 +1,239 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::time::{sleep, Instant};
+
+use super::docker::DockerRuntime;
+use super::seed::docker_reachable_url;
+use crate::backends::SyncStatus;
+use crate::config::ReplicationConfig;
+
+const INITIAL_SYNC_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Target of replication commands: a running branch container.
+pub struct SyncTarget<'a> {
+    pub runtime: &'a DockerRuntime,
+    pub container_name: &'a str,
+    pub pg_user: &'a str,
+    pub pg_db: &'a str,
+}
+
+impl SyncTarget<'_> {
+    async fn psql(&self, sql: &str) -> Result<String> {
+        self.runtime
+            .psql(self.container_name, self.pg_user, self.pg_db, sql)
+            .await
+    }
+}
+
+/// Create the subscription to the upstream publication.
+/// Each data generation gets its own replication slot, so a refresh can
+/// subscribe a staging copy while the current branch is still attached.
+pub async fn create_subscription(
+    target: &SyncTarget<'_>,
+    config: &ReplicationConfig,
+    slot_suffix: &str,
+) -> Result<()> {
+    validate_identifier(&config.subscription)?;
+
+    let url = url::Url::parse(&config.upstream)
+        .with_context(|| format!("Invalid upstream URL: {}", config.upstream))?;
+    let conninfo = docker_reachable_url(&url)?.to_string();
+    let slot_name = format!(
+        "{}_{}",
+        config.subscription,
+        slot_suffix
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .take(8)
+            .collect::<String>()
+            .to_lowercase()
+    );
+
+    let sql = format!(
+        "CREATE SUBSCRIPTION {} CONNECTION {} PUBLICATION {} WITH (copy_data = true, slot_name = {})",
+        config.subscription,
+        quote_literal(&conninfo),
+        quote_ident(&config.publication),
+        quote_literal(&slot_name),
+    );
+    target
+        .psql(&sql)
+        .await
+        .with_context(|| format!("failed to create subscription '{}'", config.subscription))?;
+
+    Ok(())
+}
+
+/// Wait until every subscribed table has finished its initial copy.
+pub async fn wait_initial_sync(target: &SyncTarget<'_>, subscription: &str) -> Result<()> {
+    let deadline = Instant::now() + INITIAL_SYNC_TIMEOUT;
+    let sql = format!(
+        "SELECT count(*) FILTER (WHERE r.srsubstate NOT IN ('r', 's')) \
+         FROM pg_subscription_rel r JOIN pg_subscription s ON s.oid = r.srsubid \
+         WHERE s.subname = {}",
+        quote_literal(subscription)
+    );
+
+    loop {
+        let pending: i64 = target
+            .psql(&sql)
+            .await?
+            .trim()
+            .parse()
+            .context("unexpected output while checking subscription sync state")?;
+        if pending == 0 {
+            return Ok(());
+        }
+
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "timed out waiting for initial sync of subscription '{subscription}' ({pending} tables pending)"
+            ));
+        }
+
+        log::info!("Waiting for initial sync: {} tables pending", pending);
+        sleep(Duration::from_secs(2)).await;
+    }
+}
+
+async fn subscription_exists(target: &SyncTarget<'_>, subscription: &str) -> Result<bool> {
+    let out = target
+        .psql(&format!(
+            "SELECT count(*) FROM pg_subscription WHERE subname = {}",
+            quote_literal(subscription)
+        ))
+        .await?;
+    Ok(out.trim() != "0")
+}
+
+/// Pause or resume applying upstream changes. Returns false if there is no subscription.
+pub async fn set_enabled(
+    target: &SyncTarget<'_>,
+    subscription: &str,
+    enabled: bool,
+) -> Result<bool> {
+    validate_identifier(subscription)?;
+    if !subscription_exists(target, subscription).await? {
+        return Ok(false);
+    }
+
+    let action = if enabled { "ENABLE" } else { "DISABLE" };
+    target
+        .psql(&format!("ALTER SUBSCRIPTION {subscription} {action}"))
+        .await
+        .with_context(|| format!("failed to {action} subscription '{subscription}'"))?;
+    Ok(true)
+}
+
+/// Remove the subscription and its upstream replication slot.
+pub async fn drop_subscription(target: &SyncTarget<'_>, subscription: &str) -> Result<()> {
+    validate_identifier(subscription)?;
+    if !subscription_exists(target, subscription).await? {
+        return Ok(());
+    }
+
+    target
+        .psql(&format!("DROP SUBSCRIPTION {subscription}"))
+        .await
+        .with_context(|| format!("failed to drop subscription '{subscription}'"))?;
+    Ok(())
+}
+
+/// Drop a subscription inherited by a cloned branch without touching the
+/// upstream slot, which still belongs to the parent.
+pub async fn detach_clone(target: &SyncTarget<'_>, subscription: &str) -> Result<()> {
+    validate_identifier(subscription)?;
+    if !subscription_exists(target, subscription).await? {
+        return Ok(());
+    }
+
+    target
+        .psql(&format!(
+            "ALTER SUBSCRIPTION {subscription} DISABLE; \
+             ALTER SUBSCRIPTION {subscription} SET (slot_name = NONE); \
+             DROP SUBSCRIPTION {subscription};"
+        ))
+        .await
+        .with_context(|| format!("failed to detach subscription '{subscription}' from clone"))?;
+    Ok(())
+}
+
+/// Report whether the subscription's apply worker is running and how far behind it is.
+pub async fn status(
+    target: &SyncTarget<'_>,
+    branch_name: &str,
+    subscription: &str,
+) -> Result<SyncStatus> {
+    let out = target
+        .psql(&format!(
+            "SELECT s.subenabled, st.pid IS NOT NULL, \
+             extract(epoch FROM now() - st.latest_end_time) \
+             FROM pg_subscription s \
+             LEFT JOIN pg_stat_subscription st ON st.subid = s.oid AND st.relid IS NULL \
+             WHERE s.subname = {}",
+            quote_literal(subscription)
+        ))
+        .await?;
+
+    let line = out.lines().next().unwrap_or_default();
+    if line.is_empty() {
+        return Ok(SyncStatus {
+            branch: branch_name.to_string(),
+            subscription: subscription.to_string(),
+            enabled: false,
+            worker_running: false,
+            lag_seconds: None,
+        });
+    }
+
+    let fields: Vec<&str> = line.split('|').collect();
+    Ok(SyncStatus {
+        branch: branch_name.to_string(),
+        subscription: subscription.to_string(),
+        enabled: fields.first() == Some(&"t"),
+        worker_running: fields.get(1) == Some(&"t"),
+        lag_seconds: fields.get(2).and_then(|v| v.parse().ok()),
+    })
+}
+
+fn validate_identifier(name: &str) -> Result<()> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        anyhow::bail!(
+            "Invalid subscription name '{}': use lowercase letters, digits and underscores",
+            name
+        );
+    }
+    Ok(())
+}
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn quote_ident(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscription_names_are_plain_identifiers() {
+        assert!(validate_identifier("pgbranch_sync").is_ok());
+        assert!(validate_identifier("_sub2").is_ok());
+        assert!(validate_identifier("").is_err());
+        assert!(validate_identifier("2sub").is_err());
+        assert!(validate_identifier("Sync").is_err());
+        assert!(validate_identifier("sub-1").is_err());
+        assert!(validate_identifier("sub; DROP TABLE users").is_err());
+    }
+}
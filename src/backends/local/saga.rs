@@ -0,0 +1,213 @@
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+
+use super::docker::ContainerStatus;
+use super::model::{self, BranchState, CreateJournal, CreateStep};
+use super::LocalBackend;
+
+/// A branch creation in progress. Each step is journaled before it is taken,
+/// so the branch can be torn down after an error, a signal, or a crash.
+pub struct CreateSaga {
+    journal: CreateJournal,
+}
+
+impl CreateSaga {
+    pub fn begin(
+        backend: &LocalBackend,
+        project_id: &str,
+        branch_id: &str,
+        branch_name: &str,
+    ) -> Result<Self> {
+        let journal = CreateJournal {
+            branch_id: branch_id.to_string(),
+            project_id: project_id.to_string(),
+            branch_name: branch_name.to_string(),
+            steps: Vec::new(),
+            started_at: model::now_epoch_millis(),
+        };
+        backend.store().save_create_journal(&journal)?;
+        Ok(Self { journal })
+    }
+
+    pub fn journal(&self) -> &CreateJournal {
+        &self.journal
+    }
+
+    /// Journal a step that is about to be taken.
+    pub fn record(&mut self, backend: &LocalBackend, step: CreateStep) -> Result<()> {
+        self.journal.steps.push(step);
+        backend.store().save_create_journal(&self.journal)
+    }
+
+    /// Drop a step that has already been undone, like a pause that ended.
+    pub fn resolve(&mut self, backend: &LocalBackend, step: &CreateStep) -> Result<()> {
+        self.journal.steps.retain(|s| s != step);
+        backend.store().save_create_journal(&self.journal)
+    }
+
+    /// Remember where the storage step put the branch's data.
+    pub fn set_storage_metadata(
+        &mut self,
+        backend: &LocalBackend,
+        metadata: Option<String>,
+    ) -> Result<()> {
+        for step in &mut self.journal.steps {
+            if let CreateStep::Storage {
+                storage_metadata, ..
+            } = step
+            {
+                *storage_metadata = metadata.clone();
+            }
+        }
+        backend.store().save_create_journal(&self.journal)
+    }
+
+    pub fn finish(self, backend: &LocalBackend) -> Result<()> {
+        backend
+            .store()
+            .delete_create_journal(&self.journal.branch_id)
+    }
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix, naming the signal.
+pub async fn interrupted() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = term.recv() => "SIGTERM",
+            },
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
+
+impl LocalBackend {
+    /// Undo a creation's steps, newest first. A failing step is logged and
+    /// the rest still run.
+    pub(super) async fn undo_create(&self, project: &model::Project, journal: &CreateJournal) {
+        for step in journal.steps.iter().rev() {
+            if let Err(err) = self.undo_create_step(project, journal, step).await {
+                log::warn!(
+                    "Failed to undo {:?} for branch '{}': {:#}",
+                    step,
+                    journal.branch_name,
+                    err
+                );
+            }
+        }
+        if let Err(err) = self.store().delete_create_journal(&journal.branch_id) {
+            log::warn!("Failed to clear create journal: {:#}", err);
+        }
+    }
+
+    async fn undo_create_step(
+        &self,
+        project: &model::Project,
+        journal: &CreateJournal,
+        step: &CreateStep,
+    ) -> Result<()> {
+        match step {
+            CreateStep::PausedSync { parent_branch } => {
+                let parent = self
+                    .store()
+                    .get_branch_by_name(&project.id, parent_branch)?;
+                if let Some(parent) = parent {
+                    self.resume_sync(&parent).await;
+                }
+            }
+            CreateStep::PausedParent { container_name } => {
+                if self.runtime.container_status(container_name).await? == ContainerStatus::Paused {
+                    self.runtime.unpause_branch(container_name).await?;
+                }
+            }
+            CreateStep::Storage {
+                data_dir,
+                storage_metadata,
+            } => {
+                let branch = model::Branch {
+                    id: journal.branch_id.clone(),
+                    project_id: project.id.clone(),
+                    name: journal.branch_name.clone(),
+                    parent_branch_id: None,
+                    state: BranchState::Provisioning,
+                    data_dir: data_dir.clone(),
+                    container_name: String::new(),
+                    port: 0,
+                    storage_metadata: storage_metadata.clone(),
+                    created_at: journal.started_at,
+                    seed_status: None,
+                    last_used_at: None,
+                    image: None,
+                    port_pinned: false,
+                    data_changed_at: None,
+                };
+                self.storage.delete_branch_data(project, &branch).await?;
+            }
+            CreateStep::Row => self.store().delete_branch(&journal.branch_id)?,
+            CreateStep::Container { container_name } => {
+                self.runtime.remove_branch(container_name).await?;
+                if self.unix_sockets {
+                    let _ = tokio::fs::remove_dir_all(self.socket_dir(&journal.branch_name)).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Tear down creations that an earlier process did not finish or undo,
+    /// e.g. because it was killed. Creations this process started are its
+    /// own to undo. The caller holds the project lock.
+    pub(super) async fn recover_unfinished_creates(&self, project: &model::Project) -> Result<()> {
+        let journals = self.store().list_create_journals(&project.id, None)?;
+        for journal in journals {
+            if journal.started_at >= self.started_at {
+                continue;
+            }
+            log::info!(
+                "Cleaning up an unfinished creation of '{}'...",
+                journal.branch_name
+            );
+            self.undo_create(project, &journal).await;
+        }
+        Ok(())
+    }
+
+    /// Recover unfinished creations once per process, unless another process
+    /// holds the project lock and may still be creating.
+    pub(super) async fn recover_at_startup(&self, project: &model::Project) {
+        if self.recovered.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let result = async {
+            if self
+                .store()
+                .list_create_journals(&project.id, None)?
+                .iter()
+                .all(|journal| journal.started_at >= self.started_at)
+            {
+                return Ok(());
+            }
+            let Some(_lock) = self.try_lock_project()? else {
+                log::debug!("Project is locked; leaving unfinished creations to its holder");
+                return Ok(());
+            };
+            self.recover_unfinished_creates(project).await
+        }
+        .await;
+        if let Err(err) = result {
+            log::warn!("Failed to clean up unfinished creations: {:#}", err);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::docker::PGDATA_CONTAINER_PATH;
+
+/// Where a branch's settings directory is mounted in its container.
+pub const CONTAINER_DIR: &str = "/etc/pgbranch/conf";
+
+const CONFIG_FILE: &str = "postgresql.conf";
+
+/// The server setting that points PostgreSQL at the generated config file.
+pub fn config_file_setting() -> (String, String) {
+    (
+        "config_file".to_string(),
+        format!("{CONTAINER_DIR}/{CONFIG_FILE}"),
+    )
+}
+
+/// Write the config file a branch server reads: the data directory's own
+/// `postgresql.conf`, then `settings` on top. Settings are kept out of the
+/// command line so a reload can change them.
+pub fn write_config(dir: &Path, settings: &[(String, String)]) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create settings directory {}", dir.display()))?;
+    let path = dir.join(CONFIG_FILE);
+    // Rewritten in place: the running server sees the same file on reload
+    std::fs::write(&path, render(settings))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn render(settings: &[(String, String)]) -> String {
+    let mut out = String::from("# Generated by pgbranch; changes are overwritten\n");
+    out.push_str(&format!(
+        "include_if_exists = {}\n",
+        quote(&format!("{PGDATA_CONTAINER_PATH}/postgresql.conf"))
+    ));
+    for (name, value) in settings {
+        out.push_str(&format!("{} = {}\n", name, quote(value)));
+    }
+    out
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_settings_after_the_data_directory_config() {
+        let settings = vec![
+            ("work_mem".to_string(), "64MB".to_string()),
+            ("search_path".to_string(), r"'$user', C:\x".to_string()),
+        ];
+        assert_eq!(
+            render(&settings),
+            "# Generated by pgbranch; changes are overwritten\n\
+             include_if_exists = '/var/lib/postgresql/data/postgresql.conf'\n\
+             work_mem = '64MB'\n\
+             search_path = '''$user'', C:\\\\x'\n"
+        );
+    }
+}
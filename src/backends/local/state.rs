@@ -1,9 +1,17 @@
+use std::collections::{BTreeMap, HashSet};
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::Context;
+use chrono::DateTime;
 use rusqlite::Connection;
 
-use super::model::{now_epoch_millis, Branch, BranchState, Project, StorageBackend};
+use super::migrations;
+use super::model::{
+    now_epoch_millis, Branch, BranchState, CreateJournal, Project, SeedStatus, StorageBackend,
+    UpgradeSnapshot,
+};
+use crate::history::{BranchEvent, EventOutcome};
 
 #[derive(Debug)]
 pub struct NewProject {
@@ -11,6 +19,7 @@ pub struct NewProject {
     pub image: String,
     pub storage_backend: StorageBackend,
     pub storage_config: Option<String>,
+    pub password: Option<String>,
 }
 
 #[derive(Debug)]
@@ -24,72 +33,45 @@ pub struct NewBranch {
     pub container_name: String,
     pub port: u16,
     pub storage_metadata: Option<String>,
+    /// Image override; `None` runs the project image
+    pub image: Option<String>,
+    pub port_pinned: bool,
 }
 
+/// How long a write waits for another process's transaction to finish
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct Store {
     conn: Connection,
 }
 
 impl Store {
     pub fn open(path: &Path) -> anyhow::Result<Self> {
-        let conn = Connection::open(path)
+        let mut conn = Connection::open(path)
             .with_context(|| format!("failed to open SQLite db at {}", path.display()))?;
-
-        let store = Self { conn };
-        store.init_schema()?;
-        Ok(store)
+        // Other pgbranch processes (hooks, editors) may use the database too:
+        // WAL lets readers run during writes, and writers wait their turn
+        conn.busy_timeout(BUSY_TIMEOUT)
+            .context("failed to set SQLite busy timeout")?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+            .context("failed to enable SQLite WAL mode")?;
+
+        conn.execute_batch("PRAGMA foreign_keys = ON;")
+            .context("failed to enable SQLite foreign keys")?;
+        migrations::migrate(&mut conn, path)?;
+
+        Ok(Self { conn })
     }
 
-    fn init_schema(&self) -> anyhow::Result<()> {
-        self.conn
-            .execute_batch(
-                r#"
-            PRAGMA foreign_keys = ON;
-
-            CREATE TABLE IF NOT EXISTS projects (
-              id TEXT PRIMARY KEY,
-              name TEXT NOT NULL UNIQUE,
-              image TEXT NOT NULL,
-              storage_backend TEXT NOT NULL DEFAULT 'copy',
-              storage_config TEXT NULL,
-              created_at INTEGER NOT NULL
-            );
-
-            CREATE TABLE IF NOT EXISTS branches (
-              id TEXT PRIMARY KEY,
-              project_id TEXT NOT NULL,
-              name TEXT NOT NULL,
-              parent_branch_id TEXT NULL,
-              state TEXT NOT NULL,
-              data_dir TEXT NOT NULL,
-              container_name TEXT NOT NULL,
-              port INTEGER NOT NULL,
-              storage_metadata TEXT NULL,
-              created_at INTEGER NOT NULL,
-              UNIQUE(project_id, name),
-              FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE,
-              FOREIGN KEY(parent_branch_id) REFERENCES branches(id) ON DELETE SET NULL
-            );
-            "#,
-            )
-            .context("failed to apply SQLite schema")?;
-
-        ensure_column(
-            &self.conn,
-            "projects",
-            "storage_backend",
-            "TEXT NOT NULL DEFAULT 'copy'",
-        )?;
-        ensure_column(&self.conn, "projects", "storage_config", "TEXT NULL")?;
-        ensure_column(&self.conn, "branches", "storage_metadata", "TEXT NULL")?;
-
-        Ok(())
+    /// Version of the schema this store was migrated to.
+    pub fn schema_version(&self) -> anyhow::Result<u32> {
+        migrations::user_version(&self.conn)
     }
 
     #[allow(dead_code)]
     pub fn list_projects(&self) -> anyhow::Result<Vec<Project>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, name, image, storage_backend, storage_config, created_at FROM projects ORDER BY created_at DESC"
+            "SELECT id, name, image, storage_backend, storage_config, created_at, seed_source, password FROM projects ORDER BY created_at DESC"
         )?;
 
         let rows = stmt.query_map([], |row| {
@@ -103,6 +85,8 @@ impl Store {
                 storage_backend,
                 storage_config: row.get(4)?,
                 created_at: row.get(5)?,
+                seed_source: row.get(6)?,
+                password: row.get(7)?,
             })
         })?;
 
@@ -112,7 +96,7 @@ impl Store {
 
     pub fn get_project_by_name(&self, name: &str) -> anyhow::Result<Option<Project>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, name, image, storage_backend, storage_config, created_at FROM projects WHERE name = ?1"
+            "SELECT id, name, image, storage_backend, storage_config, created_at, seed_source, password FROM projects WHERE name = ?1"
         )?;
 
         let mut rows = stmt.query([name])?;
@@ -127,6 +111,8 @@ impl Store {
                 storage_backend,
                 storage_config: row.get(4)?,
                 created_at: row.get(5)?,
+                seed_source: row.get(6)?,
+                password: row.get(7)?,
             }));
         }
 
@@ -138,8 +124,8 @@ impl Store {
         let id = uuid::Uuid::new_v4().to_string();
 
         self.conn.execute(
-            "INSERT INTO projects(id, name, image, storage_backend, storage_config, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            rusqlite::params![id, input.name, input.image, input.storage_backend.as_str(), input.storage_config, created_at],
+            "INSERT INTO projects(id, name, image, storage_backend, storage_config, created_at, password) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![id, input.name, input.image, input.storage_backend.as_str(), input.storage_config, created_at, input.password],
         ).context("failed to insert project")?;
 
         Ok(Project {
@@ -149,22 +135,47 @@ impl Store {
             storage_backend: input.storage_backend,
             storage_config: input.storage_config,
             created_at,
+            seed_source: None,
+            password: input.password,
         })
     }
 
-    pub fn next_port(&self) -> anyhow::Result<u16> {
-        let max_port: Option<u16> = self
-            .conn
-            .query_row("SELECT MAX(port) FROM branches", [], |row| row.get(0))
-            .context("failed to compute next branch port")?;
+    pub fn update_project_image(&self, project_id: &str, image: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE projects SET image = ?1 WHERE id = ?2",
+                rusqlite::params![image, project_id],
+            )
+            .context("failed to update project image")?;
+        Ok(())
+    }
 
-        Ok(max_port.map(|v| v.saturating_add(1)).unwrap_or(55432))
+    pub fn update_project_seed_source(
+        &self,
+        project_id: &str,
+        seed_source: &str,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE projects SET seed_source = ?1 WHERE id = ?2",
+                rusqlite::params![seed_source, project_id],
+            )
+            .context("failed to update project seed source")?;
+        Ok(())
+    }
+
+    /// Ports assigned to branches of any project.
+    pub fn used_ports(&self) -> anyhow::Result<HashSet<u16>> {
+        let mut stmt = self.conn.prepare("SELECT port FROM branches")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<Result<HashSet<_>, _>>()
+            .context("failed to list branch ports")
     }
 
     pub fn list_branches(&self, project_id: &str) -> anyhow::Result<Vec<Branch>> {
         let mut stmt = self.conn.prepare(
             r#"
-            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at
+            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at, seed_status, last_used_at, image, port_pinned, data_changed_at
             FROM branches
             WHERE project_id = ?1
             ORDER BY created_at DESC
@@ -180,7 +191,7 @@ impl Store {
     pub fn list_all_branches(&self) -> anyhow::Result<Vec<Branch>> {
         let mut stmt = self.conn.prepare(
             r#"
-            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at
+            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at, seed_status, last_used_at, image, port_pinned, data_changed_at
             FROM branches
             ORDER BY created_at DESC
             "#,
@@ -198,7 +209,7 @@ impl Store {
     ) -> anyhow::Result<Option<Branch>> {
         let mut stmt = self.conn.prepare(
             r#"
-            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at
+            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at, seed_status, last_used_at, image, port_pinned, data_changed_at
             FROM branches
             WHERE project_id = ?1 AND name = ?2
             "#,
@@ -217,13 +228,13 @@ impl Store {
 
         self.conn.execute(
             r#"
-            INSERT INTO branches(id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            INSERT INTO branches(id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at, last_used_at, image, port_pinned)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?10, ?11, ?12)
             "#,
             rusqlite::params![
                 input.id, input.project_id, input.name, input.parent_branch_id,
                 input.state.as_str(), input.data_dir, input.container_name, input.port,
-                input.storage_metadata, created_at,
+                input.storage_metadata, created_at, input.image, input.port_pinned,
             ],
         ).context("failed to insert branch")?;
 
@@ -238,6 +249,11 @@ impl Store {
             port: input.port,
             storage_metadata: input.storage_metadata,
             created_at,
+            seed_status: None,
+            last_used_at: Some(created_at),
+            image: input.image,
+            port_pinned: input.port_pinned,
+            data_changed_at: None,
         })
     }
 
@@ -251,6 +267,51 @@ impl Store {
         Ok(())
     }
 
+    /// Change a branch's state only if it still has `name` and `from` state.
+    /// Returns whether it did, so processes racing for a branch agree on a winner.
+    pub fn update_branch_state_if(
+        &self,
+        branch_id: &str,
+        name: &str,
+        from: BranchState,
+        to: BranchState,
+    ) -> anyhow::Result<bool> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE branches SET state = ?1 WHERE id = ?2 AND name = ?3 AND state = ?4",
+                rusqlite::params![to.as_str(), branch_id, name, from.as_str()],
+            )
+            .context("failed to update branch state")?;
+        Ok(changed == 1)
+    }
+
+    /// Rename a running branch that no `create` is still working on, as one
+    /// statement. Returns false when the branch was renamed, stopped or taken
+    /// by another process first.
+    pub fn claim_branch(
+        &self,
+        branch_id: &str,
+        old_name: &str,
+        new_name: &str,
+        container_name: &str,
+    ) -> anyhow::Result<bool> {
+        let now = now_epoch_millis();
+        let changed = self
+            .conn
+            .execute(
+                r#"
+                UPDATE branches
+                SET name = ?1, container_name = ?2, created_at = ?3, last_used_at = ?3
+                WHERE id = ?4 AND name = ?5 AND state = 'running'
+                  AND NOT EXISTS (SELECT 1 FROM create_journal WHERE branch_id = ?4)
+                "#,
+                rusqlite::params![new_name, container_name, now, branch_id, old_name],
+            )
+            .with_context(|| format!("failed to rename branch '{}' to '{}'", old_name, new_name))?;
+        Ok(changed == 1)
+    }
+
     pub fn update_branch_storage_metadata(
         &self,
         branch_id: &str,
@@ -265,6 +326,358 @@ impl Store {
         Ok(())
     }
 
+    /// Point a branch at a new data directory in a single statement, so the
+    /// swap is atomic from the point of view of other pgbranch processes.
+    pub fn update_branch_data(
+        &self,
+        branch_id: &str,
+        data_dir: &str,
+        storage_metadata: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET data_dir = ?1, storage_metadata = ?2, data_changed_at = ?3 WHERE id = ?4",
+                rusqlite::params![data_dir, storage_metadata, now_epoch_millis(), branch_id],
+            )
+            .context("failed to update branch data dir")?;
+        Ok(())
+    }
+
+    /// Record that the branch's data was replaced in place.
+    pub fn mark_branch_data_changed(&self, branch_id: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET data_changed_at = ?1 WHERE id = ?2",
+                rusqlite::params![now_epoch_millis(), branch_id],
+            )
+            .context("failed to record branch data change")?;
+        Ok(())
+    }
+
+    /// Move a branch to another port, unpinning it when `unpin` is set.
+    pub fn update_branch_port(
+        &self,
+        branch_id: &str,
+        port: u16,
+        unpin: bool,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET port = ?1, port_pinned = port_pinned AND NOT ?2 WHERE id = ?3",
+                rusqlite::params![port, unpin, branch_id],
+            )
+            .context("failed to update branch port")?;
+        Ok(())
+    }
+
+    pub fn update_branch_image(&self, branch_id: &str, image: Option<&str>) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET image = ?1 WHERE id = ?2",
+                rusqlite::params![image, branch_id],
+            )
+            .context("failed to update branch image")?;
+        Ok(())
+    }
+
+    /// Record that a branch was just used (switched to, connected to, started).
+    pub fn touch_branch(&self, branch_id: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET last_used_at = ?1 WHERE id = ?2",
+                rusqlite::params![now_epoch_millis(), branch_id],
+            )
+            .context("failed to update branch last used time")?;
+        Ok(())
+    }
+
+    pub fn update_branch_seed_status(
+        &self,
+        branch_id: &str,
+        seed_status: SeedStatus,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET seed_status = ?1 WHERE id = ?2",
+                rusqlite::params![seed_status.as_str(), branch_id],
+            )
+            .context("failed to update branch seed status")?;
+        Ok(())
+    }
+
+    pub fn clear_branch_seed_status(&self, branch_id: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET seed_status = NULL WHERE id = ?1",
+                [branch_id],
+            )
+            .context("failed to clear branch seed status")?;
+        Ok(())
+    }
+
+    /// Remember the pre-upgrade copy of a branch, replacing any previous one.
+    pub fn save_upgrade_snapshot(&self, snapshot: &UpgradeSnapshot) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO upgrade_snapshots(branch_id, snapshot_id, data_dir, storage_metadata, image, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    snapshot.branch_id,
+                    snapshot.snapshot_id,
+                    snapshot.data_dir,
+                    snapshot.storage_metadata,
+                    snapshot.image,
+                    snapshot.created_at,
+                ],
+            )
+            .context("failed to save upgrade snapshot")?;
+        Ok(())
+    }
+
+    pub fn get_upgrade_snapshot(&self, branch_id: &str) -> anyhow::Result<Option<UpgradeSnapshot>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT branch_id, snapshot_id, data_dir, storage_metadata, image, created_at
+             FROM upgrade_snapshots WHERE branch_id = ?1",
+        )?;
+        let mut rows = stmt.query([branch_id])?;
+        if let Some(row) = rows.next()? {
+            return Ok(Some(UpgradeSnapshot {
+                branch_id: row.get(0)?,
+                snapshot_id: row.get(1)?,
+                data_dir: row.get(2)?,
+                storage_metadata: row.get(3)?,
+                image: row.get(4)?,
+                created_at: row.get(5)?,
+            }));
+        }
+        Ok(None)
+    }
+
+    pub fn delete_upgrade_snapshot(&self, branch_id: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "DELETE FROM upgrade_snapshots WHERE branch_id = ?1",
+                [branch_id],
+            )
+            .context("failed to delete upgrade snapshot")?;
+        Ok(())
+    }
+
+    pub fn save_create_journal(&self, journal: &CreateJournal) -> anyhow::Result<()> {
+        let steps =
+            serde_json::to_string(&journal.steps).context("failed to serialize create journal")?;
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO create_journal(branch_id, project_id, branch_name, steps, started_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![
+                    journal.branch_id,
+                    journal.project_id,
+                    journal.branch_name,
+                    steps,
+                    journal.started_at,
+                ],
+            )
+            .context("failed to save create journal")?;
+        Ok(())
+    }
+
+    /// Journals of unfinished creates of one branch or all of them, oldest first.
+    pub fn list_create_journals(
+        &self,
+        project_id: &str,
+        branch_name: Option<&str>,
+    ) -> anyhow::Result<Vec<CreateJournal>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT branch_id, project_id, branch_name, steps, started_at
+             FROM create_journal WHERE project_id = ?1 AND (?2 IS NULL OR branch_name = ?2)
+             ORDER BY started_at",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![project_id, branch_name], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        })?;
+
+        let mut journals = Vec::new();
+        for row in rows {
+            let (branch_id, project_id, branch_name, steps, started_at) = row?;
+            journals.push(CreateJournal {
+                steps: serde_json::from_str(&steps)
+                    .with_context(|| format!("invalid create journal for '{}'", branch_name))?,
+                branch_id,
+                project_id,
+                branch_name,
+                started_at,
+            });
+        }
+        Ok(journals)
+    }
+
+    pub fn delete_create_journal(&self, branch_id: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "DELETE FROM create_journal WHERE branch_id = ?1",
+                [branch_id],
+            )
+            .context("failed to delete create journal")?;
+        Ok(())
+    }
+
+    pub fn insert_event(&self, project: &str, event: &BranchEvent) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO events (project, branch, operation, timestamp, os_user, git_user, git_branch, git_commit, duration_ms, outcome, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                rusqlite::params![
+                    project,
+                    event.branch,
+                    event.operation,
+                    event.timestamp.timestamp_millis(),
+                    event.os_user,
+                    event.git_user,
+                    event.git_branch,
+                    event.git_commit,
+                    event.duration_ms as i64,
+                    event.outcome.as_str(),
+                    event.error,
+                ],
+            )
+            .context("failed to record event")?;
+        Ok(())
+    }
+
+    /// Events of a project, or of one of its branches, newest first.
+    pub fn list_events(
+        &self,
+        project: &str,
+        branch: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<BranchEvent>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT branch, operation, timestamp, os_user, git_user, git_branch, git_commit, duration_ms, outcome, error FROM events WHERE project = ?1 AND (?2 IS NULL OR branch = ?2) ORDER BY id DESC LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![project, branch, limit as i64], |row| {
+            let timestamp: i64 = row.get(2)?;
+            let duration_ms: i64 = row.get(7)?;
+            let outcome: String = row.get(8)?;
+            Ok(BranchEvent {
+                timestamp: DateTime::from_timestamp_millis(timestamp).unwrap_or_default(),
+                operation: row.get(1)?,
+                project: None,
+                database: Some(project.to_string()),
+                branch: row.get(0)?,
+                os_user: row.get(3)?,
+                git_user: row.get(4)?,
+                git_branch: row.get(5)?,
+                git_commit: row.get(6)?,
+                duration_ms: duration_ms as u64,
+                outcome: EventOutcome::from_str(&outcome),
+                error: row.get(9)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .context("failed to list events")
+    }
+
+    /// Replace the record of init scripts that ran for a project.
+    pub fn record_init_scripts(
+        &self,
+        project_id: &str,
+        scripts: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM init_scripts WHERE project_id = ?1",
+            [project_id],
+        )
+        .context("failed to clear init scripts")?;
+        let applied_at = now_epoch_millis();
+        for (name, checksum) in scripts {
+            tx.execute(
+                "INSERT INTO init_scripts(project_id, name, checksum, applied_at) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![project_id, name, checksum, applied_at],
+            )
+            .context("failed to record init script")?;
+        }
+        tx.commit().context("failed to record init scripts")?;
+        Ok(())
+    }
+
+    /// Init scripts recorded for a project, as name -> checksum.
+    pub fn list_init_scripts(&self, project_id: &str) -> anyhow::Result<BTreeMap<String, String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, checksum FROM init_scripts WHERE project_id = ?1")?;
+        let rows = stmt.query_map([project_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<BTreeMap<_, _>, _>>()
+            .context("failed to list init scripts")
+    }
+
+    /// Per-branch PostgreSQL setting overrides, ordered by name.
+    pub fn list_branch_settings(&self, branch_id: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, value FROM branch_settings WHERE branch_id = ?1 ORDER BY name",
+        )?;
+        let rows = stmt.query_map([branch_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .context("failed to list branch settings")
+    }
+
+    pub fn set_branch_setting(
+        &self,
+        branch_id: &str,
+        name: &str,
+        value: &str,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO branch_settings(branch_id, name, value) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(branch_id, name) DO UPDATE SET value = excluded.value",
+                rusqlite::params![branch_id, name, value],
+            )
+            .context("failed to save branch setting")?;
+        Ok(())
+    }
+
+    /// Remove a per-branch setting. Returns false if it was not set.
+    pub fn unset_branch_setting(&self, branch_id: &str, name: &str) -> anyhow::Result<bool> {
+        let removed = self
+            .conn
+            .execute(
+                "DELETE FROM branch_settings WHERE branch_id = ?1 AND name = ?2",
+                rusqlite::params![branch_id, name],
+            )
+            .context("failed to remove branch setting")?;
+        Ok(removed > 0)
+    }
+
+    /// Replace all per-branch settings, e.g. to roll back a failed change.
+    pub fn replace_branch_settings(
+        &self,
+        branch_id: &str,
+        settings: &[(String, String)],
+    ) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM branch_settings WHERE branch_id = ?1",
+            [branch_id],
+        )
+        .context("failed to clear branch settings")?;
+        for (name, value) in settings {
+            tx.execute(
+                "INSERT INTO branch_settings(branch_id, name, value) VALUES (?1, ?2, ?3)",
+                rusqlite::params![branch_id, name, value],
+            )
+            .context("failed to save branch setting")?;
+        }
+        tx.commit().context("failed to replace branch settings")?;
+        Ok(())
+    }
+
     pub fn delete_branch(&self, branch_id: &str) -> anyhow::Result<()> {
         self.conn
             .execute("DELETE FROM branches WHERE id = ?1", [branch_id])
@@ -296,25 +709,184 @@ fn map_branch_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Branch> {
         port: row.get(7)?,
         storage_metadata: row.get(8)?,
         created_at: row.get(9)?,
+        seed_status: row
+            .get::<_, Option<String>>(10)?
+            .as_deref()
+            .and_then(SeedStatus::from_str),
+        last_used_at: row.get(11)?,
+        image: row.get(12)?,
+        port_pinned: row.get(13)?,
+        data_changed_at: row.get(14)?,
     })
 }
 
-fn ensure_column(
-    conn: &Connection,
-    table: &str,
-    column: &str,
-    column_definition: &str,
-) -> anyhow::Result<()> {
-    let pragma = format!("PRAGMA table_info({table})");
-    let mut stmt = conn.prepare(&pragma)?;
-    let rows = stmt.query_map([], |row| row.get::<_, String>(1))?;
-    for row in rows {
-        if row?.eq_ignore_ascii_case(column) {
-            return Ok(());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::local::model::CreateStep;
+    use chrono::Utc;
+
+    /// A store in a temporary directory holding one project named `app`.
+    fn store_with_project() -> (tempfile::TempDir, Store, Project) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::open(&dir.path().join("state.db")).unwrap();
+        let project = store
+            .create_project(NewProject {
+                name: "app".to_string(),
+                image: "postgres:17".to_string(),
+                storage_backend: StorageBackend::Copy,
+                storage_config: None,
+                password: None,
+            })
+            .unwrap();
+        (dir, store, project)
+    }
+
+    /// A running root branch of `project` named `name`.
+    fn new_branch(project: &Project, name: &str) -> NewBranch {
+        NewBranch {
+            id: format!("{name}-id"),
+            project_id: project.id.clone(),
+            name: name.to_string(),
+            parent_branch_id: None,
+            state: BranchState::Running,
+            data_dir: format!("/tmp/{name}/pgdata"),
+            container_name: format!("pgbranch-app-{name}"),
+            port: 55432,
+            storage_metadata: None,
+            image: None,
+            port_pinned: false,
         }
     }
 
-    let alter = format!("ALTER TABLE {table} ADD COLUMN {column} {column_definition}");
-    conn.execute(&alter, [])?;
-    Ok(())
+    #[test]
+    fn create_journals_round_trip() {
+        let (_dir, store, project) = store_with_project();
+
+        let journal = CreateJournal {
+            branch_id: "b1".to_string(),
+            project_id: project.id.clone(),
+            branch_name: "feature".to_string(),
+            steps: vec![
+                CreateStep::Storage {
+                    data_dir: "/tmp/b1/pgdata".to_string(),
+                    storage_metadata: None,
+                },
+                CreateStep::Row,
+            ],
+            started_at: 1,
+        };
+        store.save_create_journal(&journal).unwrap();
+
+        let journals = store
+            .list_create_journals(&project.id, Some("feature"))
+            .unwrap();
+        assert_eq!(journals.len(), 1);
+        assert_eq!(journals[0].steps, journal.steps);
+        assert!(store
+            .list_create_journals(&project.id, Some("other"))
+            .unwrap()
+            .is_empty());
+
+        store.delete_create_journal("b1").unwrap();
+        assert!(store
+            .list_create_journals(&project.id, Some("feature"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn moving_a_port_unpins_only_when_asked() {
+        let (_dir, store, project) = store_with_project();
+        let branch = store
+            .create_branch(NewBranch {
+                port_pinned: true,
+                ..new_branch(&project, "feature")
+            })
+            .unwrap();
+        let stored = |store: &Store| {
+            store
+                .get_branch_by_name(&project.id, &branch.name)
+                .unwrap()
+                .unwrap()
+        };
+
+        store.update_branch_port(&branch.id, 55433, false).unwrap();
+        let moved = stored(&store);
+        assert_eq!(moved.port, 55433);
+        assert!(moved.port_pinned);
+
+        store.update_branch_port(&branch.id, 55434, true).unwrap();
+        let repaired = stored(&store);
+        assert_eq!(repaired.port, 55434);
+        assert!(!repaired.port_pinned);
+    }
+
+    #[test]
+    fn warm_branches_are_claimed_once() {
+        let (_dir, store, project) = store_with_project();
+        let warm = store
+            .create_branch(new_branch(&project, "_warm_1"))
+            .unwrap();
+
+        assert!(store
+            .claim_branch(&warm.id, &warm.name, "feature", "pgbranch-app-feature")
+            .unwrap());
+        assert!(!store
+            .claim_branch(&warm.id, &warm.name, "other", "pgbranch-app-other")
+            .unwrap());
+        let claimed = store
+            .get_branch_by_name(&project.id, "feature")
+            .unwrap()
+            .unwrap();
+        assert_eq!(claimed.container_name, "pgbranch-app-feature");
+
+        // A clone being removed can no longer be claimed
+        assert!(store
+            .update_branch_state_if(
+                &warm.id,
+                "feature",
+                BranchState::Running,
+                BranchState::Provisioning
+            )
+            .unwrap());
+        assert!(!store
+            .claim_branch(&warm.id, "feature", "other", "pgbranch-app-other")
+            .unwrap());
+    }
+
+    #[test]
+    fn events_are_listed_newest_first() {
+        let (_dir, store, _project) = store_with_project();
+        let event = |operation: &str, branch: Option<&str>| BranchEvent {
+            timestamp: Utc::now(),
+            operation: operation.to_string(),
+            project: None,
+            database: None,
+            branch: branch.map(str::to_string),
+            os_user: Some("dev".to_string()),
+            git_user: None,
+            git_branch: Some("feature".to_string()),
+            git_commit: None,
+            duration_ms: 12,
+            outcome: EventOutcome::Ok,
+            error: None,
+        };
+        store
+            .insert_event("app", &event("create", Some("feature")))
+            .unwrap();
+        store
+            .insert_event("app", &event("reset", Some("feature")))
+            .unwrap();
+        store.insert_event("app", &event("cleanup", None)).unwrap();
+        store
+            .insert_event("other", &event("delete", Some("feature")))
+            .unwrap();
+
+        let events = store.list_events("app", Some("feature"), 10).unwrap();
+        let operations: Vec<&str> = events.iter().map(|e| e.operation.as_str()).collect();
+        assert_eq!(operations, ["reset", "create"]);
+        assert_eq!(events[0].actor(), "dev");
+        assert_eq!(store.list_events("app", None, 2).unwrap().len(), 2);
+    }
 }
@@ -187,6 +187,16 @@ impl LocalDriver {
         Ok(())
     }
 
+    /// Total size of the files under `data_dir`, or `None` if it is gone.
+    /// Clones that share blocks with their parent (APFS, reflinks) are
+    /// counted in full.
+    pub async fn dir_size(&self, data_dir: &Path) -> anyhow::Result<Option<u64>> {
+        let data_dir = data_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || directory_size(&data_dir))
+            .await?
+            .context("failed to measure branch data")
+    }
+
     pub async fn remove_dir(&self, data_dir: &Path) -> anyhow::Result<()> {
         let branch_root = branch_root_from_data_dir(data_dir)?;
         if tokio::fs::metadata(branch_root).await.is_ok() {
@@ -200,6 +210,38 @@ impl LocalDriver {
     }
 }
 
+fn directory_size(path: &Path) -> std::io::Result<Option<u64>> {
+    let mut total = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                if dir == path {
+                    return Ok(None);
+                }
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let entry = entry?;
+            // PostgreSQL removes files while it runs; skip what vanished
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                total += metadata.len();
+            }
+        }
+    }
+    Ok(Some(total))
+}
+
 fn branch_root_from_data_dir(data_dir: &Path) -> anyhow::Result<&Path> {
     data_dir
         .parent()
@@ -223,6 +265,8 @@ async fn recreate_dir(path: &Path) -> anyhow::Result<()> {
 async fn run_cp(args: Vec<OsString>) -> anyhow::Result<()> {
     let output = Command::new("cp")
         .args(args)
+        // An interrupted create drops this future; stop copying before undo deletes the target
+        .kill_on_drop(true)
         .output()
         .await
         .context("failed to execute cp command")?;
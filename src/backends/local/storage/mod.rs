@@ -230,6 +230,43 @@ impl StorageCoordinator {
         }
     }
 
+    /// Remove data a branch no longer uses. On ZFS it stays until the last
+    /// clone of it is deleted.
+    pub async fn retire_branch_data(
+        &self,
+        project: &Project,
+        branch: &Branch,
+    ) -> anyhow::Result<()> {
+        match project.storage_backend {
+            StorageBackend::Zfs => {
+                let config = parse_zfs_config(project)?;
+                self.zfs.retire_branch(project, &config, branch).await
+            }
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.delete_branch_data(project, branch).await
+            }
+        }
+    }
+
+    /// Space the branch's data takes up, or `None` once it is gone.
+    pub async fn branch_size(
+        &self,
+        project: &Project,
+        branch: &Branch,
+    ) -> anyhow::Result<Option<u64>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => {
+                let config = parse_zfs_config(project)?;
+                self.zfs.used(project, &config, branch).await
+            }
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local
+                    .dir_size(std::path::PathBuf::from(&branch.data_dir).as_path())
+                    .await
+            }
+        }
+    }
+
     pub async fn delete_project_data(&self, project: &Project) -> anyhow::Result<()> {
         match project.storage_backend {
             StorageBackend::Zfs => {
@@ -222,13 +222,42 @@ impl ZfsDriver {
         ))
     }
 
+    /// Space used by the branch's dataset, or `None` if it is gone.
+    pub async fn used(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+        branch: &Branch,
+    ) -> anyhow::Result<Option<u64>> {
+        let dataset = match branch.storage_metadata {
+            Some(_) => parse_zfs_branch_metadata(branch)?.dataset,
+            None => branch_dataset_name(config, &project.id, &branch.id),
+        };
+        let output = zfs_output(["get", "-Hp", "-o", "value", "used", &dataset]).await?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let used = String::from_utf8_lossy(&output.stdout);
+        used.trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("unexpected 'zfs get used' output: {}", used.trim()))
+    }
+
     pub async fn delete_branch(
         &self,
-        _project: &Project,
-        _config: &ZfsProjectConfig,
+        project: &Project,
+        config: &ZfsProjectConfig,
         branch: &Branch,
     ) -> anyhow::Result<()> {
-        let metadata = parse_zfs_branch_metadata(branch)?;
+        let metadata = match branch.storage_metadata {
+            Some(_) => parse_zfs_branch_metadata(branch)?,
+            // Creation was interrupted before the clone reported its dataset
+            None => ZfsBranchMetadata {
+                dataset: branch_dataset_name(config, &project.id, &branch.id),
+                origin_snapshot: None,
+            },
+        };
 
         let _ = zfs_output_os(vec![
             OsString::from("destroy"),
@@ -238,7 +267,17 @@ impl ZfsDriver {
         .await;
 
         if let Some(snapshot) = metadata.origin_snapshot {
-            let _ = zfs_output_os(vec![OsString::from("destroy"), OsString::from(snapshot)]).await;
+            let _ = zfs_output_os(vec![
+                OsString::from("destroy"),
+                OsString::from(snapshot.clone()),
+            ])
+            .await;
+            // The parent's data may have been kept only for this clone
+            if let Some((origin_dataset, _)) = snapshot.split_once('@') {
+                if let Err(e) = destroy_retired(origin_dataset).await {
+                    log::warn!("failed to clean up retired dataset '{origin_dataset}': {e}");
+                }
+            }
         }
 
         let branch_root = branch_root_from_data_dir(Path::new(&branch.data_dir))?;
@@ -250,6 +289,84 @@ impl ZfsDriver {
 
         Ok(())
     }
+
+    /// Mark the dataset a refreshed branch used to live on as retired. It is
+    /// destroyed now if nothing was cloned from it, or else once the last
+    /// clone is deleted.
+    pub async fn retire_branch(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+        branch: &Branch,
+    ) -> anyhow::Result<()> {
+        let dataset = match branch.storage_metadata {
+            Some(_) => parse_zfs_branch_metadata(branch)?.dataset,
+            None => branch_dataset_name(config, &project.id, &branch.id),
+        };
+        zfs_output_os(vec![
+            OsString::from("set"),
+            OsString::from(format!("{RETIRED_PROPERTY}=on")),
+            OsString::from(dataset.clone()),
+        ])
+        .await
+        .with_context(|| format!("failed to retire ZFS dataset '{dataset}'"))?
+        .success_or_stderr()?;
+
+        destroy_retired(&dataset).await
+    }
+}
+
+/// User property marking a dataset that only clones still depend on.
+const RETIRED_PROPERTY: &str = "pgbranch:retired";
+
+/// Destroy `dataset` if it is retired and no clones depend on it any more,
+/// then do the same for the dataset it was itself cloned from.
+async fn destroy_retired(dataset: &str) -> anyhow::Result<()> {
+    let mut next = Some(dataset.to_string());
+    while let Some(dataset) = next.take() {
+        if zfs_property(&dataset, RETIRED_PROPERTY).await?.as_deref() != Some("on") {
+            break;
+        }
+        let origin = zfs_property(&dataset, "origin").await?;
+        let mountpoint = zfs_property(&dataset, "mountpoint").await?;
+
+        // Without -R this refuses while clones of its snapshots remain
+        let output = zfs_output(["destroy", "-r", &dataset]).await?;
+        if !output.status.success() {
+            log::debug!(
+                "keeping retired dataset '{}': {}",
+                dataset,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            break;
+        }
+
+        if let Some(mountpoint) = mountpoint.filter(|m| m.starts_with('/')) {
+            if tokio::fs::metadata(&mountpoint).await.is_ok() {
+                tokio::fs::remove_dir_all(&mountpoint)
+                    .await
+                    .with_context(|| format!("failed to remove '{mountpoint}'"))?;
+            }
+        }
+
+        if let Some(snapshot) = origin {
+            let _ = zfs_output(["destroy", &snapshot]).await;
+            next = snapshot
+                .split_once('@')
+                .map(|(parent, _)| parent.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// A dataset property's value, or `None` if it is unset or the dataset is gone.
+async fn zfs_property(dataset: &str, property: &str) -> anyhow::Result<Option<String>> {
+    let output = zfs_output(["get", "-H", "-o", "value", property, dataset]).await?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!value.is_empty() && value != "-").then_some(value))
 }
 
 fn detect_dataset_from_mountpoints(projects_root: &Path, zfs_list_output: &str) -> Option<String> {
@@ -385,6 +502,7 @@ async fn dataset_exists(dataset: &str) -> anyhow::Result<bool> {
 async fn zfs_output<const N: usize>(args: [&str; N]) -> anyhow::Result<std::process::Output> {
     Command::new("zfs")
         .args(args)
+        .kill_on_drop(true)
         .output()
         .await
         .context("failed to execute zfs command")
@@ -393,6 +511,7 @@ async fn zfs_output<const N: usize>(args: [&str; N]) -> anyhow::Result<std::proc
 async fn zfs_output_os(args: Vec<OsString>) -> anyhow::Result<std::process::Output> {
     Command::new("zfs")
         .args(args)
+        .kill_on_drop(true)
         .output()
         .await
         .context("failed to execute zfs command")
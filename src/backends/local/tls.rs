@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    Issuer, KeyPair, KeyUsagePurpose,
+};
+
+/// Where the certificate directory is mounted in branch containers.
+pub const CONTAINER_DIR: &str = "/etc/pgbranch/tls";
+
+const CA_CERT: &str = "ca.crt";
+const SERVER_CERT: &str = "server.crt";
+const SERVER_KEY: &str = "server.key";
+/// Host names the server certificate was issued for, one per line
+const HOSTS: &str = "hosts";
+
+/// Where the server key is copied in branch containers. It is not read from
+/// the mount: PostgreSQL only accepts a key owned by the user it runs as.
+pub const KEY_CONTAINER_PATH: &str = "/etc/pgbranch/server.key";
+
+/// Path of the CA certificate clients should trust (`sslrootcert`).
+pub fn ca_cert_path(dir: &Path) -> PathBuf {
+    dir.join(CA_CERT)
+}
+
+/// Settings that turn on TLS with the mounted server certificate.
+pub fn server_settings() -> Vec<(String, String)> {
+    vec![
+        ("ssl".to_string(), "on".to_string()),
+        (
+            "ssl_cert_file".to_string(),
+            format!("{CONTAINER_DIR}/{SERVER_CERT}"),
+        ),
+        ("ssl_key_file".to_string(), KEY_CONTAINER_PATH.to_string()),
+    ]
+}
+
+/// The server key, to copy into branch containers.
+pub fn server_key(dir: &Path) -> Result<Vec<u8>> {
+    let path = dir.join(SERVER_KEY);
+    std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Generate a project CA and a server certificate for `hosts` in `dir`,
+/// unless they already exist for the same hosts. The CA key is not kept:
+/// certificates are regenerated together when the hosts change or the
+/// directory is removed.
+pub fn ensure(dir: &Path, project_name: &str, hosts: &[String]) -> Result<()> {
+    let recorded = std::fs::read_to_string(dir.join(HOSTS)).unwrap_or_default();
+    let complete = ca_cert_path(dir).exists()
+        && dir.join(SERVER_CERT).exists()
+        && dir.join(SERVER_KEY).exists();
+    if complete && recorded.lines().eq(hosts.iter().map(String::as_str)) {
+        return Ok(());
+    }
+    if complete {
+        log::info!(
+            "Regenerating TLS certificates for {}; clients must trust the new CA",
+            hosts.join(", ")
+        );
+    }
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create TLS directory {}", dir.display()))?;
+
+    let mut ca_params = CertificateParams::default();
+    ca_params.distinguished_name = DistinguishedName::new();
+    ca_params.distinguished_name.push(
+        DnType::CommonName,
+        format!("pgbranch local CA ({project_name})"),
+    );
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let ca_key = KeyPair::generate().context("failed to generate CA key")?;
+    let ca_cert = ca_params
+        .self_signed(&ca_key)
+        .context("failed to create CA certificate")?;
+    let issuer = Issuer::new(ca_params, ca_key);
+
+    let mut server_params = CertificateParams::new(hosts.to_vec())
+        .context("invalid host name for server certificate")?;
+    server_params.distinguished_name.push(
+        DnType::CommonName,
+        hosts.first().cloned().unwrap_or_default(),
+    );
+    server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let server_key = KeyPair::generate().context("failed to generate server key")?;
+    let server_cert = server_params
+        .signed_by(&server_key, &issuer)
+        .context("failed to sign server certificate")?;
+
+    write_file(&dir.join(SERVER_KEY), &server_key.serialize_pem(), 0o600)?;
+    write_file(&dir.join(SERVER_CERT), &server_cert.pem(), 0o644)?;
+    write_file(&ca_cert_path(dir), &ca_cert.pem(), 0o644)?;
+    write_file(&dir.join(HOSTS), &(hosts.join("\n") + "\n"), 0o644)?;
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &str, mode: u32) -> Result<()> {
+    std::fs::write(path, contents)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    // PostgreSQL refuses a key readable by others
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("failed to set permissions on {}", path.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_certificates_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let hosts = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        ensure(dir.path(), "demo", &hosts).unwrap();
+
+        let ca = std::fs::read_to_string(ca_cert_path(dir.path())).unwrap();
+        assert!(ca.starts_with("-----BEGIN CERTIFICATE-----"));
+        let key = std::fs::read_to_string(dir.path().join(SERVER_KEY)).unwrap();
+        assert!(key.contains("PRIVATE KEY"));
+
+        ensure(dir.path(), "demo", &hosts).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(ca_cert_path(dir.path())).unwrap(),
+            ca
+        );
+    }
+
+    #[test]
+    fn regenerates_certificates_when_hosts_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut hosts = vec!["localhost".to_string()];
+        ensure(dir.path(), "demo", &hosts).unwrap();
+        let key = server_key(dir.path()).unwrap();
+
+        hosts.push("192.168.1.20".to_string());
+        ensure(dir.path(), "demo", &hosts).unwrap();
+        assert_ne!(server_key(dir.path()).unwrap(), key);
+    }
+}
@@ -0,0 +1,228 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::docker::{DockerRuntime, HelperSpec};
+
+const UPGRADE_MOUNT: &str = "/upgrade";
+const NEW_DATA_SUFFIX: &str = ".new";
+
+/// Runs inside the helper image, which carries both sets of binaries.
+/// The new cluster is initialized with the old cluster's checksum setting,
+/// upgraded, and given the old authentication config.
+const UPGRADE_SCRIPT: &str = r#"set -eu
+if ! getent passwd "$(id -u)" >/dev/null 2>&1; then
+  export LD_PRELOAD=libnss_wrapper.so NSS_WRAPPER_PASSWD="$(mktemp)" NSS_WRAPPER_GROUP="$(mktemp)"
+  echo "postgres:x:$(id -u):$(id -g):PostgreSQL:$PWD:/bin/false" > "$NSS_WRAPPER_PASSWD"
+  echo "postgres:x:$(id -g):" > "$NSS_WRAPPER_GROUP"
+fi
+OLD_BIN="/usr/lib/postgresql/$OLD_MAJOR/bin"
+NEW_BIN="/usr/lib/postgresql/$NEW_MAJOR/bin"
+rm -rf "$NEW_DATA"
+if [ "$("$OLD_BIN/pg_controldata" "$OLD_DATA" | sed -n 's/^Data page checksum version: *//p')" = 0 ]; then
+  if "$NEW_BIN/initdb" --help | grep -q -- --no-data-checksums; then CHECKSUMS=--no-data-checksums; else CHECKSUMS=; fi
+else
+  CHECKSUMS=--data-checksums
+fi
+"$NEW_BIN/initdb" -D "$NEW_DATA" -U "$PGUSER" $CHECKSUMS
+"$NEW_BIN/pg_upgrade" -b "$OLD_BIN" -B "$NEW_BIN" -d "$OLD_DATA" -D "$NEW_DATA" -U "$PGUSER" "--$MODE"
+cp "$OLD_DATA/pg_hba.conf" "$OLD_DATA/pg_ident.conf" "$NEW_DATA/"
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeMode {
+    Link,
+    Clone,
+    Copy,
+}
+
+impl UpgradeMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Link => "link",
+            Self::Clone => "clone",
+            Self::Copy => "copy",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "link" => Some(Self::Link),
+            "clone" => Some(Self::Clone),
+            "copy" => Some(Self::Copy),
+            _ => None,
+        }
+    }
+}
+
+/// How a parent's data reaches a branch running another major version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Migration {
+    /// Same major version: the CoW clone runs as-is
+    None,
+    /// Newer server: pg_upgrade the clone
+    Upgrade { old_major: u32, new_major: u32 },
+    /// Older server: pg_upgrade cannot go backwards, so dump and restore
+    DumpRestore,
+}
+
+impl Migration {
+    pub fn between(old_major: u32, new_major: u32) -> Self {
+        match new_major.cmp(&old_major) {
+            std::cmp::Ordering::Equal => Self::None,
+            std::cmp::Ordering::Greater => Self::Upgrade {
+                old_major,
+                new_major,
+            },
+            std::cmp::Ordering::Less => Self::DumpRestore,
+        }
+    }
+}
+
+/// Helper image with both the old and new PostgreSQL binaries.
+pub fn default_helper_image(old_major: u32, new_major: u32) -> String {
+    format!("tianon/postgres-upgrade:{old_major}-to-{new_major}")
+}
+
+/// Major version of the cluster in a data directory.
+pub fn data_dir_major(data_dir: &Path) -> Result<u32> {
+    let path = data_dir.join("PG_VERSION");
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    text.trim()
+        .parse()
+        .with_context(|| format!("unexpected contents in {}", path.display()))
+}
+
+/// Fail unless the cluster in a data directory was shut down cleanly, as
+/// pg_upgrade requires. A killed server leaves its `postmaster.pid` behind.
+pub fn ensure_shut_down(data_dir: &Path) -> Result<()> {
+    if data_dir.join("postmaster.pid").exists() {
+        anyhow::bail!(
+            "the cluster in {} was not shut down cleanly; start and stop the branch before upgrading",
+            data_dir.display()
+        );
+    }
+    Ok(())
+}
+
+pub struct UpgradeJob<'a> {
+    pub runtime: &'a DockerRuntime,
+    pub helper_image: &'a str,
+    pub data_dir: &'a Path,
+    pub old_major: u32,
+    pub new_major: u32,
+    pub pg_user: &'a str,
+    pub mode: UpgradeMode,
+}
+
+impl UpgradeJob<'_> {
+    fn new_data_dir(&self) -> PathBuf {
+        let mut name = self.data_dir.file_name().unwrap_or_default().to_os_string();
+        name.push(NEW_DATA_SUFFIX);
+        self.data_dir.with_file_name(name)
+    }
+
+    /// Run pg_upgrade into a sibling directory and swap it into place.
+    /// The sibling shares the branch's filesystem, so `--link` works with
+    /// every storage driver.
+    pub async fn run(&self) -> Result<()> {
+        let branch_root = self
+            .data_dir
+            .parent()
+            .context("branch data directory has no parent")?;
+        let old_name = self
+            .data_dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let new_data_dir = self.new_data_dir();
+        ensure_shut_down(self.data_dir)?;
+
+        let (exit_code, output) = self
+            .runtime
+            .run_helper(&HelperSpec {
+                image: self.helper_image.to_string(),
+                user: None,
+                env: vec![
+                    format!("OLD_MAJOR={}", self.old_major),
+                    format!("NEW_MAJOR={}", self.new_major),
+                    format!("OLD_DATA={UPGRADE_MOUNT}/{old_name}"),
+                    format!("NEW_DATA={UPGRADE_MOUNT}/{old_name}{NEW_DATA_SUFFIX}"),
+                    format!("PGUSER={}", self.pg_user),
+                    format!("MODE={}", self.mode.as_str()),
+                ],
+                binds: vec![format!("{}:{UPGRADE_MOUNT}", branch_root.display())],
+                cmd: vec![
+                    "bash".to_string(),
+                    "-c".to_string(),
+                    format!("cd {UPGRADE_MOUNT} && {UPGRADE_SCRIPT}"),
+                ],
+            })
+            .await?;
+
+        if exit_code != 0 {
+            self.abort().await;
+            anyhow::bail!(
+                "pg_upgrade failed (exit code {}):\n{}",
+                exit_code,
+                output.trim()
+            );
+        }
+        log::debug!("pg_upgrade output:\n{}", output);
+
+        let mut old_name = self.data_dir.file_name().unwrap_or_default().to_os_string();
+        old_name.push(".old");
+        let retired = self.data_dir.with_file_name(old_name);
+        tokio::fs::rename(self.data_dir, &retired)
+            .await
+            .with_context(|| format!("failed to move {}", self.data_dir.display()))?;
+        tokio::fs::rename(&new_data_dir, self.data_dir)
+            .await
+            .with_context(|| format!("failed to move {}", new_data_dir.display()))?;
+        if let Err(e) = tokio::fs::remove_dir_all(&retired).await {
+            log::warn!(
+                "Failed to remove old cluster at {}: {}",
+                retired.display(),
+                e
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Put the old cluster back in a usable state after a failed run.
+    async fn abort(&self) {
+        let _ = tokio::fs::remove_dir_all(self.new_data_dir()).await;
+
+        // With --link, pg_upgrade disables the old cluster once linking starts
+        let global = self.data_dir.join("global");
+        if tokio::fs::metadata(global.join("pg_control.old"))
+            .await
+            .is_ok()
+        {
+            if let Err(e) =
+                tokio::fs::rename(global.join("pg_control.old"), global.join("pg_control")).await
+            {
+                log::warn!("Failed to restore pg_control after failed upgrade: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_requires_a_cleanly_stopped_cluster() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("PG_VERSION"), "16\n").unwrap();
+        ensure_shut_down(dir.path()).unwrap();
+
+        // What a killed server leaves behind
+        std::fs::write(dir.path().join("postmaster.pid"), "42\n").unwrap();
+        let err = ensure_shut_down(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("not shut down cleanly"));
+    }
+}
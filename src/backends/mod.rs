@@ -15,6 +15,8 @@ use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::history::{self, BranchEvent};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BranchInfo {
     pub name: String,
@@ -23,6 +25,16 @@ pub struct BranchInfo {
     pub database_name: String,
     #[serde(default)]
     pub state: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Last time the branch was started or switched to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// Size of the branch's data on disk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +45,13 @@ pub struct ConnectionInfo {
     pub user: String,
     pub password: Option<String>,
     pub connection_string: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sslmode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sslrootcert: Option<String>,
+    /// Host directory holding the server's Unix socket (usable as PGHOST)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_dir: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +61,170 @@ pub struct ProjectInfo {
     pub image: Option<String>,
 }
 
+/// Extra settings for `create` (local backend).
+#[derive(Debug, Clone, Default)]
+pub struct CreateBranchOptions {
+    /// Run the branch on this image instead of its parent's
+    pub image: Option<String>,
+    /// Pin the branch to this host port
+    pub port: Option<u16>,
+}
+
+/// Container CPU and memory use of a running branch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// Percent of one CPU, as `docker stats` reports it
+    pub cpu_percent: f64,
+    pub memory_bytes: u64,
+    pub memory_limit_bytes: u64,
+}
+
+/// A fix `repair` can apply to a branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepairAction {
+    /// Replace the container, keeping the branch's data
+    RecreateContainer,
+    /// Replace the branch's data with a fresh clone of its parent
+    Reclone,
+    /// Move the branch to a free port
+    RepickPort,
+    /// Remove whatever is left of the branch
+    Forget,
+}
+
+impl RepairAction {
+    pub const ALL: [RepairAction; 4] = [
+        Self::RecreateContainer,
+        Self::Reclone,
+        Self::RepickPort,
+        Self::Forget,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::RecreateContainer => "recreate-container",
+            Self::Reclone => "reclone",
+            Self::RepickPort => "repick-port",
+            Self::Forget => "forget",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.as_str() == value)
+    }
+}
+
+/// One way `repair` could fix a branch, and what it would do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepairOption {
+    pub action: RepairAction,
+    pub description: String,
+    pub steps: Vec<String>,
+    /// Why the option cannot be applied to this branch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unavailable: Option<String>,
+}
+
+/// What is wrong with a branch and how it could be fixed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepairPlan {
+    pub branch: String,
+    pub state: String,
+    pub problems: Vec<String>,
+    pub options: Vec<RepairOption>,
+}
+
+/// A branch moved to another port because its own was taken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortReassignment {
+    pub branch: String,
+    pub old_port: u16,
+    pub new_port: u16,
+}
+
+/// What `logs` shows.
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Keep streaming new lines until interrupted
+    pub follow: bool,
+    /// Only lines logged within this window
+    pub since: Option<std::time::Duration>,
+}
+
+/// How `upgrade` runs pg_upgrade.
+#[derive(Debug, Clone)]
+pub struct UpgradeOptions {
+    /// pg_upgrade transfer mode: link, clone or copy
+    pub mode: String,
+    /// Image providing both the old and new binaries
+    pub helper_image: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncStatus {
+    pub branch: String,
+    pub subscription: String,
+    pub enabled: bool,
+    pub worker_running: bool,
+    pub lag_seconds: Option<f64>,
+}
+
+/// Fill level of a backend's warm pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmPoolStatus {
+    pub size: usize,
+    pub from: String,
+    /// Running clones `create` can claim
+    pub ready: usize,
+    /// Clones still being created, or stopped
+    pub pending: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtensionVersion {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchExtensions {
+    pub branch: String,
+    pub extensions: Vec<ExtensionVersion>,
+}
+
+/// A PostgreSQL server setting applied to a branch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostgresSetting {
+    pub name: String,
+    pub value: String,
+    /// "project" for `postgres_settings` in the config, "branch" for overrides
+    pub source: String,
+}
+
+/// How changed server settings took effect on a branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsApplied {
+    /// The server was restarted, as some settings require
+    Restarted,
+    /// The server reloaded its configuration
+    Reloaded,
+    /// The branch is not running; they apply when it next starts
+    NextStart,
+}
+
+impl SettingsApplied {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingsApplied::Restarted => "restarted",
+            SettingsApplied::Reloaded => "reloaded",
+            SettingsApplied::NextStart => "next_start",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DoctorReport {
     pub checks: Vec<DoctorCheck>,
@@ -63,6 +246,15 @@ pub trait DatabaseBranchingBackend: Send + Sync {
         branch_name: &str,
         from_branch: Option<&str>,
     ) -> Result<BranchInfo>;
+    /// Create a branch with backend-specific options (image, pinned port).
+    async fn create_branch_with_options(
+        &self,
+        _branch_name: &str,
+        _from_branch: Option<&str>,
+        _options: &CreateBranchOptions,
+    ) -> Result<BranchInfo> {
+        anyhow::bail!("This backend does not support --image or --port")
+    }
     async fn delete_branch(&self, branch_name: &str) -> Result<()>;
     async fn list_branches(&self) -> Result<Vec<BranchInfo>>;
     async fn branch_exists(&self, branch_name: &str) -> Result<bool>;
@@ -71,6 +263,29 @@ pub trait DatabaseBranchingBackend: Send + Sync {
     // Connection information
     async fn get_connection_info(&self, branch_name: &str) -> Result<ConnectionInfo>;
 
+    /// Labels of each branch, keyed by branch name, for `--label` selection.
+    async fn branch_labels(
+        &self,
+    ) -> Result<std::collections::HashMap<String, std::collections::HashMap<String, String>>> {
+        anyhow::bail!("This backend does not support branch labels")
+    }
+
+    /// Resource usage of running branches, keyed by branch name.
+    async fn resource_usage(&self) -> Result<std::collections::HashMap<String, ResourceUsage>> {
+        Ok(std::collections::HashMap::new())
+    }
+
+    /// Size of each branch's data on disk, keyed by branch name. Branches
+    /// whose size is unknown are left out.
+    async fn branch_sizes(&self) -> Result<std::collections::HashMap<String, u64>> {
+        Ok(std::collections::HashMap::new())
+    }
+
+    /// Docker container serving the branch, for backends that run one.
+    async fn branch_container(&self, _branch_name: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
     // Backend-specific capabilities
     fn supports_cleanup(&self) -> bool {
         true
@@ -108,6 +323,7 @@ pub trait DatabaseBranchingBackend: Send + Sync {
             .filter(|b| b.name != "main" && b.name != "master")
             .collect();
 
+        #[allow(clippy::unnecessary_sort_by)]
         sorted_branches.sort_by(|a, b| b.created_at.cmp(&a.created_at));
 
         let mut deleted = Vec::new();
@@ -139,6 +355,132 @@ pub trait DatabaseBranchingBackend: Send + Sync {
         anyhow::bail!("This backend does not support seeding from external sources")
     }
 
+    async fn refresh_branch(&self, _branch_name: &str) -> Result<()> {
+        anyhow::bail!("This backend does not support refreshing from a seed source")
+    }
+
+    // Continuous sync from an upstream (local backend with logical replication)
+    async fn sync_status(&self) -> Result<Option<SyncStatus>> {
+        Ok(None)
+    }
+
+    // Pre-started clones claimed by `create` (local backend with `warm_pool`)
+    async fn warm_pool_status(&self) -> Result<Option<WarmPoolStatus>> {
+        Ok(None)
+    }
+
+    /// Top the warm pool up to its size. Returns the clones created.
+    async fn fill_warm_pool(&self) -> Result<Vec<String>> {
+        anyhow::bail!("This backend does not support a warm pool")
+    }
+
+    /// Remove every clone in the warm pool. Returns the clones removed.
+    async fn drain_warm_pool(&self) -> Result<Vec<String>> {
+        anyhow::bail!("This backend does not support a warm pool")
+    }
+
+    // Installed extensions per running branch (local backend with `extensions`)
+    async fn extension_versions(&self) -> Result<Vec<BranchExtensions>> {
+        Ok(vec![])
+    }
+
+    // Per-branch server settings (local backend)
+    async fn branch_settings(&self, _branch_name: &str) -> Result<Vec<PostgresSetting>> {
+        anyhow::bail!("This backend does not support per-branch server settings")
+    }
+
+    /// Store setting overrides for a branch and apply them, restoring the
+    /// previous ones if the branch cannot run with them.
+    async fn set_branch_settings(
+        &self,
+        _branch_name: &str,
+        _settings: &[(String, String)],
+    ) -> Result<SettingsApplied> {
+        anyhow::bail!("This backend does not support per-branch server settings")
+    }
+
+    /// Remove setting overrides; `None` if none of them were set.
+    async fn unset_branch_settings(
+        &self,
+        _branch_name: &str,
+        _names: &[String],
+    ) -> Result<Option<SettingsApplied>> {
+        anyhow::bail!("This backend does not support per-branch server settings")
+    }
+
+    /// Pass the branch's server log lines to `on_line`.
+    async fn stream_logs(
+        &self,
+        _branch_name: &str,
+        _options: &LogOptions,
+        _on_line: &mut (dyn for<'l> FnMut(&'l str) + Send),
+    ) -> Result<()> {
+        anyhow::bail!("This backend does not support logs")
+    }
+
+    /// Branches whose port was reassigned by this backend instance.
+    fn port_reassignments(&self) -> Vec<PortReassignment> {
+        Vec::new()
+    }
+
+    // Major-version upgrades (local backend)
+    /// Upgrade one branch, or all of them, to `image`. Returns the upgraded branch names.
+    async fn upgrade(
+        &self,
+        _image: &str,
+        _branch_name: Option<&str>,
+        _options: &UpgradeOptions,
+    ) -> Result<Vec<String>> {
+        anyhow::bail!("This backend does not support upgrades")
+    }
+
+    /// Restore branches from their pre-upgrade snapshots.
+    async fn rollback_upgrade(&self, _branch_name: Option<&str>) -> Result<Vec<String>> {
+        anyhow::bail!("This backend does not support upgrades")
+    }
+
+    /// Delete pre-upgrade snapshots once an upgrade is known to be good.
+    async fn discard_upgrade_snapshots(&self, _branch_name: Option<&str>) -> Result<Vec<String>> {
+        anyhow::bail!("This backend does not support upgrades")
+    }
+
+    // Repair of failed or stuck branches (local backend)
+    async fn repair_plans(&self, _branch_name: Option<&str>) -> Result<Vec<RepairPlan>> {
+        anyhow::bail!("This backend does not support repair")
+    }
+
+    async fn repair_branch(&self, _branch_name: &str, _action: RepairAction) -> Result<()> {
+        anyhow::bail!("This backend does not support repair")
+    }
+
+    // Event history; backends without a state store append to a JSONL file
+    async fn record_event(&self, event: &BranchEvent) -> Result<()> {
+        history::append(event)
+    }
+
+    async fn list_events(
+        &self,
+        database: &str,
+        branch_name: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<BranchEvent>> {
+        history::read(None, Some(database), branch_name, limit)
+    }
+
+    // Idle auto-stop (local backend with `idle_timeout`)
+    async fn stop_idle_branches(&self) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    fn stops_idle_branches(&self) -> bool {
+        false
+    }
+
+    /// Keep stopping idle branches until none is left running.
+    async fn watch_idle_branches(&self) -> Result<()> {
+        Ok(())
+    }
+
     // Diagnostics
     async fn doctor(&self) -> Result<DoctorReport>;
 
@@ -148,6 +148,10 @@ impl DatabaseBranchingBackend for NeonBackend {
             parent_branch: response.branch.parent_id,
             database_name: response.branch.id,
             state: Some("running".to_string()),
+            server_version: None,
+            port: None,
+            last_used_at: None,
+            size_bytes: None,
         })
     }
 
@@ -184,6 +188,10 @@ impl DatabaseBranchingBackend for NeonBackend {
                 parent_branch: branch.parent_id,
                 database_name: branch.id,
                 state: Some("running".to_string()),
+                server_version: None,
+                port: None,
+                last_used_at: None,
+                size_bytes: None,
             })
             .collect();
 
@@ -225,6 +233,9 @@ impl DatabaseBranchingBackend for NeonBackend {
             user: endpoint.database_user,
             password: endpoint.database_password,
             connection_string: Some(connection_string),
+            sslmode: None,
+            sslrootcert: None,
+            socket_dir: None,
         })
     }
 
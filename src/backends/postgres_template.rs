@@ -1,7 +1,9 @@
-use super::{BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport};
+use super::{
+    BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport, LogOptions,
+};
 use crate::config::Config;
 use crate::database::DatabaseManager;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use chrono::Utc;
 
@@ -42,6 +44,10 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
             parent_branch: _from_branch.map(|s| s.to_string()),
             database_name,
             state: Some("running".to_string()),
+            server_version: None,
+            port: None,
+            last_used_at: None,
+            size_bytes: None,
         })
     }
 
@@ -60,6 +66,10 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
                 parent_branch: None,
                 database_name: self.get_branch_database_name(&name),
                 state: Some("running".to_string()),
+                server_version: None,
+                port: None,
+                last_used_at: None,
+                size_bytes: None,
             })
             .collect();
 
@@ -81,6 +91,10 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
             parent_branch: None,
             database_name,
             state: Some("running".to_string()),
+            server_version: None,
+            port: None,
+            last_used_at: None,
+            size_bytes: None,
         })
     }
 
@@ -113,9 +127,75 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
             user: self.config.database.user.clone(),
             password: self.config.database.password.clone(),
             connection_string: Some(connection_string),
+            sslmode: None,
+            sslrootcert: None,
+            socket_dir: None,
         })
     }
 
+    /// Reads the server log with `pg_read_binary_file`, which needs superuser
+    /// or `pg_read_server_files`. Branches share one server, so the log covers
+    /// all of them. Lines are decoded here, as chunks can end inside a
+    /// multi-byte character.
+    async fn stream_logs(
+        &self,
+        _branch_name: &str,
+        options: &LogOptions,
+        on_line: &mut (dyn for<'l> FnMut(&'l str) + Send),
+    ) -> Result<()> {
+        const CHUNK_SIZE: i64 = 1024 * 1024;
+
+        if options.since.is_some() {
+            log::warn!("--since is not supported by the template backend; showing the whole log");
+        }
+
+        let client = self.db_manager.connect().await?;
+        let mut path = current_logfile(&client)
+            .await?
+            .context("The server is not writing a log file (logging_collector is off)")?;
+        let mut offset: i64 = 0;
+        let mut pending: Vec<u8> = Vec::new();
+        loop {
+            let chunk: Option<Vec<u8>> = client
+                .query_one(
+                    "SELECT pg_read_binary_file($1, $2, $3, true)",
+                    &[&path, &offset, &CHUNK_SIZE],
+                )
+                .await
+                .context(
+                    "Failed to read the server log (requires superuser or pg_read_server_files)",
+                )?
+                .get(0);
+            let chunk = chunk.unwrap_or_default();
+            offset += chunk.len() as i64;
+            pending.extend_from_slice(&chunk);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                on_line(String::from_utf8_lossy(&pending[..end]).trim_end_matches('\r'));
+                pending.drain(..=end);
+            }
+
+            if chunk.len() as i64 == CHUNK_SIZE {
+                continue;
+            }
+            if !options.follow {
+                break;
+            }
+
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            // Follow the server onto a new file when it rotates its log
+            if let Some(current) = current_logfile(&client).await? {
+                if current != path {
+                    path = current;
+                    offset = 0;
+                }
+            }
+        }
+        if !pending.is_empty() {
+            on_line(&String::from_utf8_lossy(&pending));
+        }
+        Ok(())
+    }
+
     async fn cleanup_old_branches(&self, max_count: usize) -> Result<Vec<String>> {
         self.db_manager.cleanup_old_branches(max_count).await?;
         Ok(vec![])
@@ -195,3 +275,11 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
         63
     }
 }
+
+async fn current_logfile(client: &tokio_postgres::Client) -> Result<Option<String>> {
+    let row = client
+        .query_one("SELECT pg_current_logfile()", &[])
+        .await
+        .context("Failed to find the server log file")?;
+    Ok(row.get(0))
+}
@@ -211,6 +211,10 @@ impl DatabaseBranchingBackend for XataBackend {
             parent_branch: from_branch.map(|s| s.to_string()),
             database_name: self.project_id.clone(),
             state: Some("running".to_string()),
+            server_version: None,
+            port: None,
+            last_used_at: None,
+            size_bytes: None,
         })
     }
 
@@ -235,6 +239,10 @@ impl DatabaseBranchingBackend for XataBackend {
                 parent_branch: None,
                 database_name: self.project_id.clone(),
                 state: Some("running".to_string()),
+                server_version: None,
+                port: None,
+                last_used_at: None,
+                size_bytes: None,
             })
             .collect())
     }
@@ -279,6 +287,9 @@ impl DatabaseBranchingBackend for XataBackend {
             user: creds.username,
             password: Some(creds.password),
             connection_string: Some(connection_string),
+            sslmode: None,
+            sslrootcert: None,
+            socket_dir: None,
         })
     }
 
@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -20,6 +21,9 @@ pub struct Config {
     pub backends: Option<Vec<NamedBackendConfig>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub worktree: Option<WorktreeConfig>,
+    /// Directory of the config file; relative paths in the config resolve against it
+    #[serde(skip)]
+    pub config_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,7 +61,7 @@ fn default_backend_type() -> String {
     "local".to_string()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct LocalBackendConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub image: Option<String>,
@@ -67,12 +71,195 @@ pub struct LocalBackendConfig {
     pub storage: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub port_range_start: Option<u16>,
+    /// Last port branches are assigned from (default: start + 999)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_range_end: Option<u16>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub postgres_user: Option<String>,
+    /// Defaults to a password generated per project
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub postgres_password: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub postgres_db: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<SeedConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replication: Option<ReplicationConfig>,
+    /// Stop branches with no client connections for this long (e.g. "30m", "2h")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<ContainerConfig>,
+    /// Extensions to install in the project image and create in every branch
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    /// Dockerfile used to build the project image (its directory is the build context)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dockerfile: Option<String>,
+    /// Directory mounted at /docker-entrypoint-initdb.d when the first branch is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_scripts: Option<String>,
+    /// PostgreSQL settings applied to every branch server
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub postgres_settings: BTreeMap<String, String>,
+    /// Per-branch container settings, merged over `container`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub branch_overrides: BTreeMap<String, ContainerConfig>,
+    /// Host address branch ports are published on (default 127.0.0.1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<String>,
+    /// Serve TLS with certificates from a generated project CA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<bool>,
+    /// Expose each branch's Unix socket in `<data_root>/sockets/<project>/<branch>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_sockets: Option<bool>,
+    /// Keep running clones ready so `create` only has to claim one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warm_pool: Option<WarmPoolConfig>,
+    /// Clone running branches within an online backup instead of pausing them (default true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub online_clones: Option<bool>,
+}
+
+/// Docker settings applied to branch containers.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ContainerConfig {
+    /// Memory limit (e.g. "512m", "2g")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    /// Number of CPUs (e.g. 1.5)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+    /// Size of /dev/shm (e.g. "1g"); Docker defaults to 64m
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shm_size: Option<String>,
+    /// Restart policy: no, always, unless-stopped or on-failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Extra bind mounts as "host_path:container_path[:ro]"; relative host
+    /// paths resolve against the config file's directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<String>,
+}
+
+impl ContainerConfig {
+    /// Layer `other` over `self`: scalar settings are replaced, env and labels
+    /// are merged by key, and mounts are appended.
+    pub fn merged_with(&self, other: &ContainerConfig) -> ContainerConfig {
+        let mut merged = self.clone();
+        if other.memory.is_some() {
+            merged.memory = other.memory.clone();
+        }
+        if other.cpus.is_some() {
+            merged.cpus = other.cpus;
+        }
+        if other.shm_size.is_some() {
+            merged.shm_size = other.shm_size.clone();
+        }
+        if other.restart.is_some() {
+            merged.restart = other.restart.clone();
+        }
+        merged.env.extend(other.env.clone());
+        merged.labels.extend(other.labels.clone());
+        merged.mounts.extend(other.mounts.iter().cloned());
+        merged
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SeedConfig {
+    /// SQL statements or `.sql` file paths run against the branch after a seed restore
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scripts: Vec<String>,
+    /// Queries that must return `true` for the seed to be marked successful
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub verify: Vec<String>,
+}
+
+/// Keep a local branch in sync with an upstream database through logical replication.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationConfig {
+    /// Connection URL of the upstream (publisher) database
+    pub upstream: String,
+    /// Name of the publication on the upstream
+    pub publication: String,
+    #[serde(default = "default_subscription_name")]
+    pub subscription: String,
+    /// Local branch that follows the upstream
+    #[serde(default = "default_main_branch")]
+    pub branch: String,
+}
+
+/// A pool of anonymous, already running clones of one branch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmPoolConfig {
+    /// Number of clones to keep ready
+    pub size: usize,
+    /// Branch the clones are taken from
+    #[serde(default = "default_main_branch")]
+    pub from: String,
+}
+
+/// Parse a duration such as "90s", "30m", "2h" or "1d". A bare number is seconds.
+pub fn parse_duration(value: &str) -> Result<std::time::Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .with_context(|| format!("Invalid duration '{}'", value))?;
+    let multiplier = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        other => anyhow::bail!(
+            "Invalid duration unit '{}' in '{}': use s, m, h or d",
+            other,
+            value
+        ),
+    };
+    let seconds = amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("Duration '{}' is too long", value))?;
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// Parse a byte size such as "512m", "2g" or "64MB". A bare number is bytes.
+pub fn parse_size(value: &str) -> Result<i64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .with_context(|| format!("Invalid size '{}'", value))?;
+    let multiplier: i64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1024,
+        "m" | "mb" => 1024 * 1024,
+        "g" | "gb" => 1024 * 1024 * 1024,
+        other => anyhow::bail!(
+            "Invalid size unit '{}' in '{}': use k, m or g",
+            other,
+            value
+        ),
+    };
+    amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("Size '{}' is too large", value))
+}
+
+fn default_subscription_name() -> String {
+    "pgbranch_sync".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -368,6 +555,7 @@ impl Default for Config {
             backend: None,
             backends: None,
             worktree: None,
+            config_dir: None,
         }
     }
 }
@@ -393,6 +581,9 @@ impl Config {
         // Handle backward compatibility: if current_branch was loaded, ignore it
         // The local state manager will handle current branch tracking
         config.current_branch = None;
+        config.config_dir = std::path::absolute(path)
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf));
 
         Ok(config)
     }
@@ -1065,3 +1256,31 @@ impl TemplateContext {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_duration, parse_size};
+    use std::time::Duration;
+
+    #[test]
+    fn parses_durations_with_units() {
+        assert_eq!(parse_duration("45").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert!(parse_duration("10y").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("18446744073709551615d").is_err());
+    }
+
+    #[test]
+    fn parses_sizes_with_units() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("512m").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("64MB").unwrap(), 64 * 1024 * 1024);
+        assert!(parse_size("1t").is_err());
+        assert!(parse_size("9223372036854775807k").is_err());
+    }
+}
@@ -240,6 +240,24 @@ fi
         .to_string()
     }
 
+    /// Abbreviated id of the commit HEAD points at.
+    pub fn head_commit(&self) -> Option<String> {
+        let commit = self.repo.head().ok()?.peel_to_commit().ok()?;
+        let id = commit.id().to_string();
+        Some(id[..12.min(id.len())].to_string())
+    }
+
+    /// `user.name <user.email>` from the Git config, or whichever is set.
+    pub fn user_identity(&self) -> Option<String> {
+        let config = self.repo.config().ok()?;
+        let name = config.get_string("user.name").ok();
+        let email = config.get_string("user.email").ok();
+        match (name, email) {
+            (Some(name), Some(email)) => Some(format!("{} <{}>", name, email)),
+            (name, email) => name.or(email),
+        }
+    }
+
     pub fn is_pgbranch_hook(&self, hook_path: &Path) -> Result<bool> {
         if !hook_path.exists() {
             return Ok(false);
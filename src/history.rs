@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::git::GitRepository;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventOutcome {
+    Ok,
+    Error,
+}
+
+impl EventOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventOutcome::Ok => "ok",
+            EventOutcome::Error => "error",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Self {
+        match value {
+            "ok" => EventOutcome::Ok,
+            _ => EventOutcome::Error,
+        }
+    }
+}
+
+/// A recorded operation on a branch, or on a whole project when `branch` is `None`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchEvent {
+    pub timestamp: DateTime<Utc>,
+    pub operation: String,
+    /// Directory of the project's config file, for the shared log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// Database (backend) name; `None` for switches recorded before it was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    pub branch: Option<String>,
+    pub os_user: Option<String>,
+    pub git_user: Option<String>,
+    pub git_branch: Option<String>,
+    pub git_commit: Option<String>,
+    pub duration_ms: u64,
+    pub outcome: EventOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BranchEvent {
+    /// Who ran the operation: the Git identity, else the OS user.
+    pub fn actor(&self) -> &str {
+        self.git_user
+            .as_deref()
+            .or(self.os_user.as_deref())
+            .unwrap_or("unknown")
+    }
+}
+
+/// Times an operation and captures who ran it from where.
+pub struct EventRecorder {
+    event: BranchEvent,
+    started: Instant,
+}
+
+impl EventRecorder {
+    pub fn start(operation: &str, database: Option<&str>, branch: Option<&str>) -> Self {
+        let git = GitRepository::new(".").ok();
+        let os_user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .ok();
+        Self {
+            event: BranchEvent {
+                timestamp: Utc::now(),
+                operation: operation.to_string(),
+                project: None,
+                database: database.map(str::to_string),
+                branch: branch.map(str::to_string),
+                os_user,
+                git_user: git.as_ref().and_then(|g| g.user_identity()),
+                git_branch: git
+                    .as_ref()
+                    .and_then(|g| g.get_current_branch().ok().flatten()),
+                git_commit: git.as_ref().and_then(|g| g.head_commit()),
+                duration_ms: 0,
+                outcome: EventOutcome::Ok,
+                error: None,
+            },
+            started: Instant::now(),
+        }
+    }
+
+    /// Tag the event with the project whose config file is at `config_path`.
+    pub fn in_project(mut self, config_path: Option<&Path>) -> Self {
+        self.event.project = config_path.and_then(project_key);
+        self
+    }
+
+    pub fn finish<T>(self, result: &Result<T>) -> BranchEvent {
+        let mut event = self.event;
+        event.duration_ms = self.started.elapsed().as_millis() as u64;
+        if let Err(err) = result {
+            event.outcome = EventOutcome::Error;
+            event.error = Some(format!("{:#}", err));
+        }
+        event
+    }
+}
+
+/// Size at which the log is rotated; one rotated file is kept
+const MAX_LOG_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Identifies a project in the shared log: the directory of its config file.
+pub fn project_key(config_path: &Path) -> Option<String> {
+    config_path
+        .parent()
+        .and_then(|dir| dir.canonicalize().ok())
+        .map(|dir| dir.to_string_lossy().to_string())
+}
+
+/// Append-only log for backends without a state store of their own.
+fn log_path() -> Result<PathBuf> {
+    let dir = dirs::config_dir()
+        .context("Failed to get user config directory")?
+        .join("pgbranch");
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create config directory: {}", dir.display()))?;
+    Ok(dir.join("history.jsonl"))
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    path.with_extension("jsonl.1")
+}
+
+pub fn append(event: &BranchEvent) -> Result<()> {
+    append_to(&log_path()?, event)
+}
+
+fn append_to(path: &Path, event: &BranchEvent) -> Result<()> {
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() >= MAX_LOG_BYTES) {
+        // A concurrent rotation may have won; either way the log starts over
+        let _ = fs::rename(path, rotated_path(path));
+    }
+    let mut line = serde_json::to_string(event)?;
+    line.push('\n');
+    // One write per line, so concurrent appends do not interleave
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to append to {}", path.display()))
+}
+
+/// Events of `database` (optionally one branch), newest first. With a
+/// `project`, events recorded for other projects are left out.
+pub fn read(
+    project: Option<&str>,
+    database: Option<&str>,
+    branch: Option<&str>,
+    limit: usize,
+) -> Result<Vec<BranchEvent>> {
+    read_from(&log_path()?, project, database, branch, limit)
+}
+
+fn read_from(
+    path: &Path,
+    project: Option<&str>,
+    database: Option<&str>,
+    branch: Option<&str>,
+    limit: usize,
+) -> Result<Vec<BranchEvent>> {
+    // Stream both files oldest first, keeping only the newest matches
+    let mut events = VecDeque::with_capacity(limit.min(1024));
+    for path in [rotated_path(path), path.to_path_buf()] {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+            let Ok(event) = serde_json::from_str::<BranchEvent>(&line) else {
+                continue;
+            };
+            if event.database.as_deref() != database
+                || branch.is_some_and(|branch| event.branch.as_deref() != Some(branch))
+                || project.is_some_and(|project| {
+                    event
+                        .project
+                        .as_deref()
+                        .is_some_and(|other| other != project)
+                })
+            {
+                continue;
+            }
+            if events.len() == limit {
+                events.pop_front();
+            }
+            if limit > 0 {
+                events.push_back(event);
+            }
+        }
+    }
+    Ok(events.into_iter().rev().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(project: &str, branch: &str) -> BranchEvent {
+        let mut recorder = EventRecorder::start("switch", Some("default"), Some(branch));
+        recorder.event.project = Some(project.to_string());
+        recorder.finish(&Ok(()))
+    }
+
+    #[test]
+    fn reads_newest_matches_across_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        fs::write(&path, vec![b'x'; MAX_LOG_BYTES as usize]).unwrap();
+        append_to(&path, &event("/a", "one")).unwrap();
+        assert!(rotated_path(&path).exists());
+        append_to(&path, &event("/b", "two")).unwrap();
+        append_to(&path, &event("/a", "three")).unwrap();
+
+        let events = read_from(&path, Some("/a"), Some("default"), None, 10).unwrap();
+        let branches: Vec<_> = events.iter().filter_map(|e| e.branch.as_deref()).collect();
+        assert_eq!(branches, ["three", "one"]);
+
+        let newest = read_from(&path, None, Some("default"), None, 1).unwrap();
+        assert_eq!(newest[0].branch.as_deref(), Some("three"));
+    }
+}
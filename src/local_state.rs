@@ -54,23 +54,22 @@ impl LocalStateManager {
             )
         })?;
 
-        // Preserve existing backends when updating current branch
-        let existing_backends = self
-            .state
-            .projects
-            .get(&project_key)
-            .and_then(|p| p.backends.clone());
-
-        let project_state = ProjectState {
-            current_branch: branch,
-            last_updated: chrono::Utc::now(),
-            backends: existing_backends,
-        };
-
-        self.state.projects.insert(project_key, project_state);
-        self.save_state()?;
-
-        Ok(())
+        self.update(|state| {
+            // Preserve existing backends when updating current branch
+            let existing_backends = state
+                .projects
+                .get(&project_key)
+                .and_then(|p| p.backends.clone());
+
+            let project_state = ProjectState {
+                current_branch: branch,
+                last_updated: chrono::Utc::now(),
+                backends: existing_backends,
+            };
+
+            state.projects.insert(project_key, project_state);
+            Ok(())
+        })
     }
 
     pub fn get_backends(&self, project_path: &Path) -> Option<Vec<NamedBackendConfig>> {
@@ -93,18 +92,19 @@ impl LocalStateManager {
             )
         })?;
 
-        let existing = self.state.projects.get(&project_key);
-        let current_branch = existing.and_then(|p| p.current_branch.clone());
+        self.update(|state| {
+            let existing = state.projects.get(&project_key);
+            let current_branch = existing.and_then(|p| p.current_branch.clone());
 
-        let project_state = ProjectState {
-            current_branch,
-            last_updated: chrono::Utc::now(),
-            backends: Some(backends),
-        };
+            let project_state = ProjectState {
+                current_branch,
+                last_updated: chrono::Utc::now(),
+                backends: Some(backends),
+            };
 
-        self.state.projects.insert(project_key, project_state);
-        self.save_state()?;
-        Ok(())
+            state.projects.insert(project_key, project_state);
+            Ok(())
+        })
     }
 
     pub fn add_backend(
@@ -120,38 +120,39 @@ impl LocalStateManager {
             )
         })?;
 
-        let existing = self.state.projects.get(&project_key);
-        let current_branch = existing.and_then(|p| p.current_branch.clone());
-        let mut backends = existing
-            .and_then(|p| p.backends.clone())
-            .unwrap_or_default();
-
-        if let Some(pos) = backends.iter().position(|b| b.name == backend.name) {
-            if force {
-                backends[pos] = backend;
+        self.update(|state| {
+            let existing = state.projects.get(&project_key);
+            let current_branch = existing.and_then(|p| p.current_branch.clone());
+            let mut backends = existing
+                .and_then(|p| p.backends.clone())
+                .unwrap_or_default();
+
+            if let Some(pos) = backends.iter().position(|b| b.name == backend.name) {
+                if force {
+                    backends[pos] = backend;
+                } else {
+                    anyhow::bail!(
+                        "Backend '{}' already exists. Use --force to overwrite.",
+                        backends[pos].name
+                    );
+                }
             } else {
-                anyhow::bail!(
-                    "Backend '{}' already exists. Use --force to overwrite.",
-                    backends[pos].name
-                );
-            }
-        } else {
-            let mut backend = backend;
-            if backends.is_empty() {
-                backend.default = true;
+                let mut backend = backend;
+                if backends.is_empty() {
+                    backend.default = true;
+                }
+                backends.push(backend);
             }
-            backends.push(backend);
-        }
 
-        let project_state = ProjectState {
-            current_branch,
-            last_updated: chrono::Utc::now(),
-            backends: Some(backends),
-        };
+            let project_state = ProjectState {
+                current_branch,
+                last_updated: chrono::Utc::now(),
+                backends: Some(backends),
+            };
 
-        self.state.projects.insert(project_key, project_state);
-        self.save_state()?;
-        Ok(())
+            state.projects.insert(project_key, project_state);
+            Ok(())
+        })
     }
 
     pub fn remove_backend(&mut self, project_path: &Path, name: &str) -> Result<()> {
@@ -162,50 +163,43 @@ impl LocalStateManager {
             )
         })?;
 
-        if let Some(project) = self.state.projects.get_mut(&project_key) {
-            if let Some(ref mut backends) = project.backends {
-                backends.retain(|b| b.name != name);
+        self.update(|state| {
+            if let Some(project) = state.projects.get_mut(&project_key) {
+                if let Some(ref mut backends) = project.backends {
+                    backends.retain(|b| b.name != name);
+                }
+                project.last_updated = chrono::Utc::now();
             }
-            project.last_updated = chrono::Utc::now();
-            self.save_state()?;
-        }
-
-        Ok(())
+            Ok(())
+        })
     }
 
     #[allow(dead_code)]
     pub fn cleanup_old_projects(&mut self, max_age_days: u32) -> Result<()> {
         let cutoff = chrono::Utc::now() - chrono::Duration::days(max_age_days as i64);
 
-        let old_projects: Vec<String> = self
-            .state
-            .projects
-            .iter()
-            .filter(|(_, project)| project.last_updated < cutoff)
-            .map(|(key, _)| key.clone())
-            .collect();
-
-        let mut projects_removed = false;
-        for project_key in &old_projects {
-            // Check if project still exists before removing
-            if let Ok(path) = PathBuf::from(&project_key).canonicalize() {
-                if !path.exists() {
-                    log::debug!("Removing state for non-existent project: {}", project_key);
-                    self.state.projects.remove(project_key);
-                    projects_removed = true;
+        self.update(|state| {
+            let old_projects: Vec<String> = state
+                .projects
+                .iter()
+                .filter(|(_, project)| project.last_updated < cutoff)
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            for project_key in &old_projects {
+                // Check if project still exists before removing
+                if let Ok(path) = PathBuf::from(&project_key).canonicalize() {
+                    if !path.exists() {
+                        log::debug!("Removing state for non-existent project: {}", project_key);
+                        state.projects.remove(project_key);
+                    }
+                } else {
+                    log::debug!("Removing state for inaccessible project: {}", project_key);
+                    state.projects.remove(project_key);
                 }
-            } else {
-                log::debug!("Removing state for inaccessible project: {}", project_key);
-                self.state.projects.remove(project_key);
-                projects_removed = true;
             }
-        }
-
-        if projects_removed {
-            self.save_state()?;
-        }
-
-        Ok(())
+            Ok(())
+        })
     }
 
     fn get_project_key(&self, project_path: &Path) -> Option<String> {
@@ -256,16 +250,45 @@ impl LocalStateManager {
         Ok(state)
     }
 
+    /// Change the state under an exclusive lock on the state file, starting
+    /// from what is on disk, so concurrent pgbranch processes do not drop each
+    /// other's changes.
+    fn update<T>(&mut self, change: impl FnOnce(&mut LocalState) -> Result<T>) -> Result<T> {
+        let lock_path = self.state_file_path.with_extension("yml.lock");
+        let lock = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open lock file: {}", lock_path.display()))?;
+        lock.lock()
+            .with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+
+        let mut state = Self::load_state(&self.state_file_path)?;
+        let result = change(&mut state)?;
+        self.state = state;
+        self.save_state()?;
+        Ok(result)
+    }
+
     fn save_state(&self) -> Result<()> {
         let content = serde_yaml_ng::to_string(&self.state)
             .context("Failed to serialize local state to YAML")?;
 
-        fs::write(&self.state_file_path, content).with_context(|| {
-            format!(
-                "Failed to write local state file: {}",
-                self.state_file_path.display()
-            )
-        })?;
+        // Write a sibling temp file and rename it over the state file, so that
+        // concurrent readers never see a partially written file
+        let temp_path = self
+            .state_file_path
+            .with_extension(format!("yml.{}.tmp", std::process::id()));
+        fs::write(&temp_path, content)
+            .and_then(|()| fs::rename(&temp_path, &self.state_file_path))
+            .with_context(|| {
+                let _ = fs::remove_file(&temp_path);
+                format!(
+                    "Failed to write local state file: {}",
+                    self.state_file_path.display()
+                )
+            })?;
 
         log::debug!("Saved local state to: {}", self.state_file_path.display());
         Ok(())
@@ -323,4 +346,39 @@ mod tests {
         manager.set_current_branch(&config_path, None).unwrap();
         assert_eq!(manager.get_current_branch(&config_path), None);
     }
+
+    #[test]
+    fn concurrent_updates_keep_each_others_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let state_file_path = temp_dir.path().join("local_state.yml");
+        let first_project = temp_dir.path().join("a");
+        let second_project = temp_dir.path().join("b");
+        fs::create_dir_all(&first_project).unwrap();
+        fs::create_dir_all(&second_project).unwrap();
+        let first_config = first_project.join(".pgbranch.yml");
+        let second_config = second_project.join(".pgbranch.yml");
+
+        // Both loaded before either writes, like two processes started together
+        let manager = || LocalStateManager {
+            state_file_path: state_file_path.clone(),
+            state: LocalStateManager::load_state(&state_file_path).unwrap(),
+        };
+        let (mut first, mut second) = (manager(), manager());
+        first
+            .set_current_branch(&first_config, Some("one".to_string()))
+            .unwrap();
+        second
+            .set_current_branch(&second_config, Some("two".to_string()))
+            .unwrap();
+
+        let reloaded = manager();
+        assert_eq!(
+            reloaded.get_current_branch(&first_config),
+            Some("one".to_string())
+        );
+        assert_eq!(
+            reloaded.get_current_branch(&second_config),
+            Some("two".to_string())
+        );
+    }
 }
@@ -8,8 +8,11 @@ mod config;
 mod database;
 mod docker;
 mod git;
+mod history;
 mod local_state;
 mod post_commands;
+#[cfg(feature = "backend-postgres-template")]
+mod top;
 
 use cli::Commands;
 
@@ -35,11 +38,22 @@ Branch Lifecycle (local backend):
   start               Start a stopped database branch container
   stop                Stop a running database branch container
   reset               Reset a database branch to its parent state
+  refresh             Re-seed a branch from its original seed source
+  stop-idle           Stop branches idle longer than local.idle_timeout
+  pool                Show, fill or drain the warm pool of ready clones
+  config-branch       Show or change PostgreSQL settings for a branch
+  upgrade             Upgrade branches to a new PostgreSQL image
+  repair              Diagnose and fix failed or stuck branches
   destroy             Destroy a database and all its branches
 
 Info:
   connection          Show connection info for a database branch
   status              Show current project and backend status
+  psql                Open a psql session on a database branch
+  exec                Run a command with a branch's connection environment
+  logs                Show the server log of a database branch
+  history             Show who did what to database branches, and when
+  top                 Show a live view of activity across branches
 
 Setup & Config:
   init                Initialize pgbranch configuration
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+use tokio_postgres::{Client, NoTls};
+
+use crate::backends::{BranchInfo, ConnectionInfo, DatabaseBranchingBackend, ResourceUsage};
+use crate::cli::format_bytes;
+
+const STATS_QUERY: &str = "
+    SELECT
+        (SELECT count(*) FROM pg_stat_activity
+         WHERE datname = current_database()
+           AND backend_type = 'client backend'
+           AND pid <> pg_backend_pid()),
+        xact_commit + xact_rollback,
+        blks_hit,
+        blks_read,
+        pg_database_size(current_database())
+    FROM pg_stat_database
+    WHERE datname = current_database()";
+
+const LONGEST_QUERY: &str = "
+    SELECT extract(epoch FROM now() - query_start)::float8, query
+    FROM pg_stat_activity
+    WHERE datname = current_database()
+      AND state <> 'idle'
+      AND pid <> pg_backend_pid()
+      AND query_start IS NOT NULL
+    ORDER BY query_start
+    LIMIT 1";
+
+/// Transactions each sample runs itself, left out of the rate.
+const OWN_XACTS: i64 = 2;
+
+const KEYS_HELP: &str = "up/down select  s start  x stop  r reset  q quit";
+
+#[derive(Debug, Default)]
+struct BranchRow {
+    name: String,
+    state: String,
+    connections: Option<i64>,
+    tps: Option<f64>,
+    cache_hit: Option<f64>,
+    size_bytes: Option<i64>,
+    usage: Option<ResourceUsage>,
+    longest_query: Option<(f64, String)>,
+}
+
+#[derive(Default)]
+struct Sampler {
+    clients: HashMap<String, Client>,
+    /// Last transaction count per branch, for transactions per second
+    xacts: HashMap<String, (i64, Instant)>,
+}
+
+impl Sampler {
+    async fn sample(&mut self, backend: &dyn DatabaseBranchingBackend) -> Result<Vec<BranchRow>> {
+        let branches = backend.list_branches().await?;
+
+        // Docker takes about a second per stats sample, so query meanwhile
+        let (usage, mut rows) = tokio::join!(backend.resource_usage(), async {
+            let mut rows = Vec::with_capacity(branches.len());
+            for branch in &branches {
+                rows.push(self.sample_branch(backend, branch).await);
+            }
+            rows
+        });
+        let mut usage = usage.unwrap_or_else(|err| {
+            log::debug!("No resource usage: {:#}", err);
+            HashMap::new()
+        });
+        for row in &mut rows {
+            row.usage = usage.remove(&row.name);
+        }
+
+        self.clients
+            .retain(|name, _| branches.iter().any(|b| &b.name == name));
+        Ok(rows)
+    }
+
+    async fn sample_branch(
+        &mut self,
+        backend: &dyn DatabaseBranchingBackend,
+        branch: &BranchInfo,
+    ) -> BranchRow {
+        let mut row = BranchRow {
+            name: branch.name.clone(),
+            state: branch.state.clone().unwrap_or_else(|| "-".to_string()),
+            ..Default::default()
+        };
+
+        // Connecting would wake a stopped branch
+        if branch
+            .state
+            .as_deref()
+            .is_some_and(|state| state != "running")
+        {
+            self.clients.remove(&branch.name);
+            self.xacts.remove(&branch.name);
+            return row;
+        }
+        if let Err(err) = self.query_branch(backend, &mut row).await {
+            log::debug!("Failed to sample branch '{}': {:#}", branch.name, err);
+            self.clients.remove(&branch.name);
+        }
+        row
+    }
+
+    async fn query_branch(
+        &mut self,
+        backend: &dyn DatabaseBranchingBackend,
+        row: &mut BranchRow,
+    ) -> Result<()> {
+        if !self.clients.contains_key(&row.name) {
+            let conn = backend.get_connection_info(&row.name).await?;
+            let client = connect(&conn).await?;
+            self.clients.insert(row.name.clone(), client);
+        }
+        let client = &self.clients[&row.name];
+
+        let stats = client.query_one(STATS_QUERY, &[]).await?;
+        let xacts: i64 = stats.get(1);
+        let blks_hit: i64 = stats.get(2);
+        let blks_read: i64 = stats.get(3);
+        row.connections = Some(stats.get(0));
+        row.size_bytes = Some(stats.get(4));
+        if blks_hit + blks_read > 0 {
+            row.cache_hit = Some(blks_hit as f64 / (blks_hit + blks_read) as f64 * 100.0);
+        }
+
+        let now = Instant::now();
+        if let Some((previous, at)) = self.xacts.insert(row.name.clone(), (xacts, now)) {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            if elapsed > 0.0 {
+                row.tps = Some((xacts - previous - OWN_XACTS).max(0) as f64 / elapsed);
+            }
+        }
+
+        row.longest_query = client
+            .query_opt(LONGEST_QUERY, &[])
+            .await?
+            .map(|longest| (longest.get(0), longest.get(1)));
+        Ok(())
+    }
+}
+
+async fn connect(conn: &ConnectionInfo) -> Result<Client> {
+    let mut config = tokio_postgres::Config::new();
+    config
+        .host(&conn.host)
+        .port(conn.port)
+        .user(&conn.user)
+        .dbname(&conn.database)
+        .application_name("pgbranch top")
+        .connect_timeout(Duration::from_secs(3));
+    if let Some(ref password) = conn.password {
+        config.password(password);
+    }
+
+    let (client, connection) = config
+        .connect(NoTls)
+        .await
+        .with_context(|| format!("Failed to connect to {}:{}", conn.host, conn.port))?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            log::debug!("Database connection error: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+/// Show a refreshing view of all branches until the user quits. When stdout
+/// is not a terminal, print a single snapshot instead.
+pub async fn run(backend: &dyn DatabaseBranchingBackend, interval: Duration) -> Result<()> {
+    let mut sampler = Sampler::default();
+
+    if !std::io::stdout().is_terminal() {
+        let rows = sampler.sample(backend).await?;
+        for line in table_lines(&rows, usize::MAX) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    terminal::enable_raw_mode().context("Failed to set up the terminal")?;
+    let mut stdout = std::io::stdout();
+    let result = match execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide) {
+        Ok(()) => event_loop(backend, &mut sampler, interval).await,
+        Err(e) => Err(e.into()),
+    };
+    let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+    result
+}
+
+async fn event_loop(
+    backend: &dyn DatabaseBranchingBackend,
+    sampler: &mut Sampler,
+    interval: Duration,
+) -> Result<()> {
+    let mut selected = 0;
+    let mut status = String::new();
+    let mut pending_reset: Option<String> = None;
+
+    loop {
+        let rows = sampler.sample(backend).await?;
+        selected = selected.min(rows.len().saturating_sub(1));
+        draw(&rows, selected, &status)?;
+
+        let deadline = Instant::now() + interval;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() || !tokio::task::block_in_place(|| event::poll(timeout))? {
+                break;
+            }
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                Event::Resize(..) => {
+                    draw(&rows, selected, &status)?;
+                    continue;
+                }
+                _ => continue,
+            };
+            let target = rows.get(selected).map(|row| row.name.clone());
+
+            if let Some(branch) = pending_reset.take() {
+                status = if key.code == KeyCode::Char('y') {
+                    match backend.reset_branch(&branch).await {
+                        Ok(()) => format!("Reset '{}'", branch),
+                        Err(e) => format!("Failed to reset '{}': {:#}", branch, e),
+                    }
+                } else {
+                    "Reset cancelled".to_string()
+                };
+                break;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    selected = (selected + 1).min(rows.len().saturating_sub(1))
+                }
+                KeyCode::Char('s') if target.is_some() => {
+                    let branch = target.unwrap_or_default();
+                    status = match backend.start_branch(&branch).await {
+                        Ok(()) => format!("Started '{}'", branch),
+                        Err(e) => format!("Failed to start '{}': {:#}", branch, e),
+                    };
+                    break;
+                }
+                KeyCode::Char('x') if target.is_some() => {
+                    let branch = target.unwrap_or_default();
+                    status = match backend.stop_branch(&branch).await {
+                        Ok(()) => format!("Stopped '{}'", branch),
+                        Err(e) => format!("Failed to stop '{}': {:#}", branch, e),
+                    };
+                    break;
+                }
+                KeyCode::Char('r') if target.is_some() => {
+                    let branch = target.unwrap_or_default();
+                    status = format!("Reset '{}' to its parent? (y/n)", branch);
+                    pending_reset = Some(branch);
+                }
+                _ => continue,
+            }
+            draw(&rows, selected, &status)?;
+        }
+    }
+}
+
+fn draw(rows: &[BranchRow], selected: usize, status: &str) -> Result<()> {
+    let (width, height) = terminal::size().unwrap_or((120, 40));
+    let (width, height) = (width as usize, height as usize);
+    let mut out = std::io::stdout().lock();
+    queue!(
+        out,
+        cursor::MoveTo(0, 0),
+        terminal::Clear(terminal::ClearType::All)
+    )?;
+
+    // Header, rows, then a blank line and the footer
+    for (i, line) in table_lines(rows, width)
+        .iter()
+        .enumerate()
+        .take(height.saturating_sub(2))
+    {
+        queue!(out, cursor::MoveTo(0, i as u16))?;
+        if i == selected + 1 {
+            queue!(
+                out,
+                SetAttribute(Attribute::Reverse),
+                Print(line),
+                SetAttribute(Attribute::Reset)
+            )?;
+        } else {
+            queue!(out, Print(line))?;
+        }
+    }
+
+    let footer = if status.is_empty() {
+        KEYS_HELP.to_string()
+    } else {
+        format!("{}  |  {}", status, KEYS_HELP)
+    };
+    queue!(
+        out,
+        cursor::MoveTo(0, height.saturating_sub(1) as u16),
+        Print(truncate(&footer, width))
+    )?;
+    out.flush()?;
+    Ok(())
+}
+
+fn table_lines(rows: &[BranchRow], width: usize) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:<24} {:<12} {:>5} {:>7} {:>6} {:>9} {:>6} {:>9}  {}",
+        "BRANCH", "STATE", "CONNS", "TPS", "CACHE", "SIZE", "CPU", "MEM", "LONGEST QUERY"
+    )];
+    for row in rows {
+        let longest = row
+            .longest_query
+            .as_ref()
+            .map(|(secs, query)| {
+                let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+                format!("{} {}", format_seconds(*secs), query)
+            })
+            .unwrap_or_default();
+        lines.push(format!(
+            "{:<24} {:<12} {:>5} {:>7} {:>6} {:>9} {:>6} {:>9}  {}",
+            truncate(&row.name, 24),
+            truncate(&row.state, 12),
+            or_dash(row.connections.map(|c| c.to_string())),
+            or_dash(row.tps.map(|tps| format!("{:.1}", tps))),
+            or_dash(row.cache_hit.map(|hit| format!("{:.1}%", hit))),
+            or_dash(row.size_bytes.map(|size| format_bytes(size as u64))),
+            or_dash(row.usage.as_ref().map(|u| format!("{:.1}%", u.cpu_percent))),
+            or_dash(row.usage.as_ref().map(|u| format_bytes(u.memory_bytes))),
+            longest
+        ));
+    }
+    lines.iter().map(|line| truncate(line, width)).collect()
+}
+
+fn or_dash(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "-".to_string())
+}
+
+fn truncate(value: &str, width: usize) -> String {
+    value.chars().take(width).collect()
+}
+
+fn format_seconds(secs: f64) -> String {
+    let secs = secs.max(0.0) as u64;
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_sizes_and_durations() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(3 * 1024 * 1024 / 2), "1.5 MiB");
+        assert_eq!(format_seconds(42.7), "42s");
+        assert_eq!(format_seconds(125.0), "2m05s");
+        assert_eq!(format_seconds(7260.0), "2h01m");
+    }
+}